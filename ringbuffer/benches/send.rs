@@ -0,0 +1,58 @@
+use agrona::RingBuffer;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const CAPACITY: usize = 64 * 1024;
+
+fn send_vs_send_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("send_vs_send_batch");
+
+    for batch_size in [1, 10, 100] {
+        let msg = [7u8; 24];
+        let messages = vec![(1, msg.as_slice()); batch_size];
+
+        group.throughput(Throughput::Elements(batch_size as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("send", batch_size),
+            &messages,
+            |b, messages| {
+                let (mut sender, mut receiver) = RingBuffer::new(CAPACITY).unwrap().split();
+
+                b.iter(|| {
+                    for &(msg_type_id, msg) in messages {
+                        sender.send(msg_type_id, black_box(msg)).unwrap();
+                    }
+                    receiver.read(
+                        |_, data| {
+                            black_box(data);
+                        },
+                        usize::MAX,
+                    );
+                });
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("send_batch", batch_size),
+            &messages,
+            |b, messages| {
+                let (mut sender, mut receiver) = RingBuffer::new(CAPACITY).unwrap().split();
+
+                b.iter(|| {
+                    sender.send_batch(black_box(messages)).unwrap();
+                    receiver.read(
+                        |_, data| {
+                            black_box(data);
+                        },
+                        usize::MAX,
+                    );
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, send_vs_send_batch);
+criterion_main!(benches);
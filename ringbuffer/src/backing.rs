@@ -0,0 +1,281 @@
+//! Memory backing a ring buffer.
+//!
+//! A [`Backing`] owns the lifetime of the memory. The ring buffer and its split halves share it
+//! through an `Arc`, so it is released exactly once, by whichever handle drops last, and in the
+//! way that matches where the memory came from.
+
+use std::{
+    alloc::{alloc_zeroed, dealloc, Layout},
+    fmt, io,
+    path::Path,
+    ptr::NonNull,
+};
+
+use crate::{mmap::MappedFile, wakers::Wakers, AERON_CACHE_LINE_LENGTH};
+#[cfg(loom)]
+use crate::{RecordDescriptor, AERON_RB_ALIGNMENT};
+
+type Deallocator = Box<dyn FnOnce(NonNull<u8>, usize) + Send>;
+
+pub struct Backing {
+    ptr: NonNull<u8>,
+    length: usize,
+    kind: Kind,
+    wakers: Wakers,
+}
+
+enum Kind {
+    Heap(Layout),
+    #[cfg(loom)]
+    LoomHeap(Layout, Box<[RecordDescriptor]>),
+    Mapped(MappedFile),
+    Borrowed,
+    Custom(Option<Deallocator>),
+}
+
+// The memory is only accessed through the ring buffer protocol, the deallocator only on drop.
+unsafe impl Send for Backing {}
+unsafe impl Sync for Backing {}
+
+impl Backing {
+    /// Allocates `length` zeroed bytes, aligned to a cache line, from the global allocator.
+    #[cfg(not(loom))]
+    pub fn heap(length: usize) -> Self {
+        assert!(length > 0);
+        let layout = Layout::from_size_align(length, AERON_CACHE_LINE_LENGTH).unwrap();
+        let ptr = unsafe { alloc_zeroed(layout) };
+
+        Self {
+            ptr: NonNull::new(ptr).unwrap(),
+            length,
+            kind: Kind::Heap(layout),
+            wakers: Wakers::new(),
+        }
+    }
+
+    /// Allocates `length` zeroed bytes, aligned to a cache line, from the global allocator.
+    ///
+    /// Under loom the record headers are kept aside, a pointer to them is stored in the cache line
+    /// in front of the memory.
+    #[cfg(loom)]
+    pub fn heap(length: usize) -> Self {
+        assert!(length > 0);
+        let layout =
+            Layout::from_size_align(AERON_CACHE_LINE_LENGTH + length, AERON_CACHE_LINE_LENGTH)
+                .unwrap();
+        let headers: Box<[RecordDescriptor]> = (0..length / AERON_RB_ALIGNMENT)
+            .map(|_| RecordDescriptor::new())
+            .collect();
+
+        unsafe {
+            let ptr = alloc_zeroed(layout).byte_add(AERON_CACHE_LINE_LENGTH);
+            (ptr as *mut *const RecordDescriptor)
+                .sub(1)
+                .write(headers.as_ptr());
+
+            Self {
+                ptr: NonNull::new(ptr).unwrap(),
+                length,
+                kind: Kind::LoomHeap(layout, headers),
+                wakers: Wakers::new(),
+            }
+        }
+    }
+
+    /// Whether ring buffer records can be placed in the memory under loom.
+    #[cfg(loom)]
+    pub(crate) fn has_record_headers(&self) -> bool {
+        matches!(self.kind, Kind::LoomHeap(..))
+    }
+
+    /// Creates (or truncates) the file at `path` and maps `length` zeroed bytes of it.
+    pub fn create_file(path: impl AsRef<Path>, length: usize) -> io::Result<Self> {
+        Ok(Self::mapped(MappedFile::create(path, length)?))
+    }
+
+    /// Maps the whole of the existing file at `path`.
+    pub fn open_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::mapped(MappedFile::open(path)?))
+    }
+
+    fn mapped(mapping: MappedFile) -> Self {
+        Self {
+            ptr: NonNull::new(mapping.as_ptr()).unwrap(),
+            length: mapping.len(),
+            kind: Kind::Mapped(mapping),
+            wakers: Wakers::new(),
+        }
+    }
+
+    /// Uses memory that lives for the rest of the program, it is never released.
+    pub fn from_static(memory: &'static mut [u8]) -> Self {
+        Self {
+            ptr: NonNull::new(memory.as_mut_ptr()).unwrap(),
+            length: memory.len(),
+            kind: Kind::Borrowed,
+            wakers: Wakers::new(),
+        }
+    }
+
+    /// Uses memory owned by someone else, it is never released.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `length` bytes that stay valid for as long as the backing is alive.
+    pub unsafe fn borrowed(ptr: *mut u8, length: usize) -> Self {
+        Self {
+            ptr: NonNull::new(ptr).unwrap(),
+            length,
+            kind: Kind::Borrowed,
+            wakers: Wakers::new(),
+        }
+    }
+
+    /// Uses user supplied memory, which is handed to `deallocator` once the last handle drops.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `length` bytes that stay valid until `deallocator` is called.
+    pub unsafe fn from_raw_parts<F>(ptr: *mut u8, length: usize, deallocator: F) -> Self
+    where
+        F: FnOnce(NonNull<u8>, usize) + Send + 'static,
+    {
+        Self {
+            ptr: NonNull::new(ptr).unwrap(),
+            length,
+            kind: Kind::Custom(Some(Box::new(deallocator))),
+            wakers: Wakers::new(),
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Wakers of the async handles of the ring buffer in the memory.
+    pub(crate) fn wakers(&self) -> &Wakers {
+        &self.wakers
+    }
+}
+
+impl Drop for Backing {
+    fn drop(&mut self) {
+        match &mut self.kind {
+            Kind::Heap(layout) => unsafe { dealloc(self.ptr.as_ptr(), *layout) },
+            #[cfg(loom)]
+            Kind::LoomHeap(layout, _) => unsafe {
+                dealloc(self.ptr.as_ptr().byte_sub(AERON_CACHE_LINE_LENGTH), *layout)
+            },
+            Kind::Custom(deallocator) => {
+                if let Some(deallocator) = deallocator.take() {
+                    deallocator(self.ptr, self.length);
+                }
+            }
+            // Unmapped when the mapping drops
+            Kind::Mapped(_) | Kind::Borrowed => {}
+        }
+    }
+}
+
+impl fmt::Debug for Backing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            Kind::Heap(_) => "Heap",
+            #[cfg(loom)]
+            Kind::LoomHeap(..) => "LoomHeap",
+            Kind::Mapped(_) => "Mapped",
+            Kind::Borrowed => "Borrowed",
+            Kind::Custom(_) => "Custom",
+        };
+
+        f.debug_struct("Backing")
+            .field("ptr", &self.ptr)
+            .field("length", &self.length)
+            .field("kind", &kind)
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::{
+        alloc::{alloc_zeroed, dealloc, Layout},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::Backing;
+    use crate::{RingBuffer, AERON_RB_TRAILER_LENGTH};
+
+    #[test]
+    fn custom_deallocator_runs_once_after_last_handle() {
+        let length = 1024 + AERON_RB_TRAILER_LENGTH;
+        let layout = Layout::from_size_align(length, 64).unwrap();
+        let deallocations = Arc::new(AtomicUsize::new(0));
+
+        let backing = unsafe {
+            let deallocations = deallocations.clone();
+            Backing::from_raw_parts(alloc_zeroed(layout), length, move |ptr, length| {
+                deallocations.fetch_add(1, Ordering::Relaxed);
+                dealloc(ptr.as_ptr(), Layout::from_size_align(length, 64).unwrap());
+            })
+        };
+
+        let (mut sender, mut receiver) = unsafe { RingBuffer::from_backing(backing) }
+            .unwrap()
+            .split();
+        let other_sender = sender.clone();
+
+        sender.send(88, &[54, 33, 77]).unwrap();
+        drop(sender);
+        assert_eq!(receiver.receive(10), [(88, vec![54, 33, 77])]);
+        drop(receiver);
+        assert_eq!(deallocations.load(Ordering::Relaxed), 0);
+
+        drop(other_sender);
+        assert_eq!(deallocations.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn invalid_backing_is_released() {
+        let deallocations = Arc::new(AtomicUsize::new(0));
+        let mut memory = vec![0u64; 128];
+
+        let backing = unsafe {
+            let deallocations = deallocations.clone();
+            Backing::from_raw_parts(memory.as_mut_ptr() as *mut u8, 1000, move |_, _| {
+                deallocations.fetch_add(1, Ordering::Relaxed);
+            })
+        };
+
+        assert!(unsafe { RingBuffer::from_backing(backing) }.is_err());
+        assert_eq!(deallocations.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn static_backing_is_never_released() {
+        let memory: &'static mut [u64] =
+            Box::leak(vec![0u64; (1024 + AERON_RB_TRAILER_LENGTH) / 8].into_boxed_slice());
+        let memory: &'static mut [u8] = unsafe {
+            std::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, memory.len() * 8)
+        };
+
+        let (mut sender, mut receiver) =
+            unsafe { RingBuffer::from_backing(Backing::from_static(memory)) }
+                .unwrap()
+                .split();
+
+        sender.send(88, &[54, 33, 77]).unwrap();
+        assert_eq!(receiver.receive(10), [(88, vec![54, 33, 77])]);
+    }
+}
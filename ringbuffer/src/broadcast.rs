@@ -0,0 +1,634 @@
+//! One to many broadcast buffer.
+//!
+//! Follows Aeron's `aeron_broadcast_transmitter` and `aeron_broadcast_receiver`: a single
+//! [`BroadcastTransmitter`] writes records into the buffer without ever waiting for anyone, and any
+//! number of [`BroadcastReceiver`]s follow it. A receiver that falls more than the capacity behind
+//! is lapped, its records are overwritten, and it skips ahead to the latest record.
+//!
+//! Records use the same header, alignment and padding as [`RingBuffer`](crate::RingBuffer)'s. The
+//! trailer is a [`BroadcastDescriptor`] of two cache lines instead of a
+//! [`RawDescriptor`](crate::descriptor).
+//!
+//! Receivers read records while the transmitter may be overwriting them, and only trust what they
+//! read once the tail intent shows it wasn't, like a seqlock. For those racing reads to be sound,
+//! every access to the records is a relaxed atomic one on a whole aligned word: a header is one
+//! word, a message as many as it spans.
+
+use std::{
+    cell::UnsafeCell,
+    fmt, io,
+    mem::{align_of, size_of},
+    path::Path,
+    ptr::NonNull,
+    sync::{
+        atomic::{fence, AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    aeron_align, aeron_rb_message_offset, backing::Backing, error::Error, is_capacity_valid,
+    sender::check_message, AERON_CACHE_LINE_LENGTH, AERON_RB_ALIGNMENT,
+    AERON_RB_PADDING_MSG_TYPE_ID, AERON_RB_RECORD_HEADER_LENGTH,
+};
+
+const WORD_LENGTH: usize = size_of::<AtomicU64>();
+
+const _: () = assert!(AERON_RB_RECORD_HEADER_LENGTH == WORD_LENGTH);
+const _: () = assert!(AERON_RB_ALIGNMENT.is_multiple_of(WORD_LENGTH));
+
+const BROADCAST_BUFFER_TRAILER_LENGTH: usize = size_of::<BroadcastDescriptor>();
+const BROADCAST_BUFFER_MIN_CAPACITY: usize = AERON_RB_RECORD_HEADER_LENGTH;
+
+/// Trailer of a broadcast buffer, after its `capacity` bytes of records.
+#[repr(C)]
+pub struct BroadcastDescriptor {
+    /// Position the transmitter is about to write up to, receivers reading behind it by more than
+    /// the capacity have been lapped.
+    pub tail_intent_counter: AtomicI64,
+    /// Position up to which records are written.
+    pub tail_counter: AtomicI64,
+    /// Position of the last record written, where lapped receivers continue.
+    pub latest_counter: AtomicI64,
+    _pad: [UnsafeCell<u8>; 2 * AERON_CACHE_LINE_LENGTH - 3 * size_of::<AtomicI64>()],
+}
+
+const _: () = assert!(BROADCAST_BUFFER_TRAILER_LENGTH == 2 * AERON_CACHE_LINE_LENGTH);
+
+/// Memory of a broadcast buffer, shared by the transmitter and the receivers.
+#[derive(Debug, Clone)]
+struct BroadcastBuffer {
+    buffer: NonNull<u8>,
+    capacity: usize,
+    descriptor: NonNull<BroadcastDescriptor>,
+    backing: Arc<Backing>,
+}
+
+impl BroadcastBuffer {
+    fn from_backing(backing: Backing) -> Result<Self, Error> {
+        let buffer = backing.as_ptr();
+        let capacity: usize = backing
+            .len()
+            .saturating_sub(BROADCAST_BUFFER_TRAILER_LENGTH);
+
+        if buffer.align_offset(align_of::<BroadcastDescriptor>()) != 0 {
+            return Err(Error::MisalignedBuffer {
+                alignment: align_of::<BroadcastDescriptor>(),
+            });
+        }
+
+        if !is_capacity_valid(capacity, BROADCAST_BUFFER_MIN_CAPACITY) {
+            return Err(Error::InvalidCapacity(capacity));
+        }
+
+        Ok(Self {
+            buffer: NonNull::new(buffer).unwrap(),
+            capacity,
+            descriptor: NonNull::new(unsafe { buffer.byte_add(capacity) }.cast()).unwrap(),
+            backing: Arc::new(backing),
+        })
+    }
+
+    fn descriptor(&self) -> &BroadcastDescriptor {
+        unsafe { self.descriptor.as_ref() }
+    }
+
+    fn record_offset(&self, position: i64) -> usize {
+        position as usize & (self.capacity - 1)
+    }
+
+    /// # Safety
+    ///
+    /// `offset` must be aligned to a word and leave room for it before the capacity.
+    unsafe fn word(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.buffer.as_ptr().byte_add(offset) as *const AtomicU64) }
+    }
+
+    /// Length and message type id of the record at `record_offset`, which must be aligned and
+    /// within the capacity.
+    fn load_header(&self, record_offset: usize) -> (i32, i32) {
+        assert!(record_offset.is_multiple_of(AERON_RB_ALIGNMENT) && record_offset < self.capacity);
+        let header = unsafe { self.word(record_offset) }
+            .load(Ordering::Relaxed)
+            .to_ne_bytes();
+
+        (
+            i32::from_ne_bytes(header[..4].try_into().unwrap()),
+            i32::from_ne_bytes(header[4..].try_into().unwrap()),
+        )
+    }
+
+    /// Writes the header of the record at `record_offset`, laid out like a ring buffer record's.
+    fn store_header(&self, record_offset: usize, length: i32, msg_type_id: i32) {
+        assert!(record_offset.is_multiple_of(AERON_RB_ALIGNMENT) && record_offset < self.capacity);
+        let mut header = [0; WORD_LENGTH];
+        header[..4].copy_from_slice(&length.to_ne_bytes());
+        header[4..].copy_from_slice(&msg_type_id.to_ne_bytes());
+
+        unsafe { self.word(record_offset) }.store(u64::from_ne_bytes(header), Ordering::Relaxed);
+    }
+
+    /// Writes `msg` after the header at `record_offset`, padding its last word with zeroes.
+    fn store_message(&self, record_offset: usize, msg: &[u8]) {
+        let offset = aeron_rb_message_offset(record_offset);
+        assert!(offset + msg.len().next_multiple_of(WORD_LENGTH) <= self.capacity);
+
+        for (index, chunk) in msg.chunks(WORD_LENGTH).enumerate() {
+            let mut word = [0; WORD_LENGTH];
+            word[..chunk.len()].copy_from_slice(chunk);
+
+            unsafe { self.word(offset + index * WORD_LENGTH) }
+                .store(u64::from_ne_bytes(word), Ordering::Relaxed);
+        }
+    }
+
+    /// Reads the start of the message after the header at `record_offset` into `dst`.
+    fn load_message(&self, record_offset: usize, dst: &mut [u8]) {
+        let offset = aeron_rb_message_offset(record_offset);
+        assert!(offset + dst.len().next_multiple_of(WORD_LENGTH) <= self.capacity);
+
+        for (index, chunk) in dst.chunks_mut(WORD_LENGTH).enumerate() {
+            let word = unsafe { self.word(offset + index * WORD_LENGTH) }
+                .load(Ordering::Relaxed)
+                .to_ne_bytes();
+
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+}
+
+/// The only writer of a broadcast buffer, hence not `Clone`.
+#[derive(Debug)]
+pub struct BroadcastTransmitter {
+    broadcast_buffer: BroadcastBuffer,
+    max_message_length: usize,
+}
+
+unsafe impl Send for BroadcastTransmitter {}
+
+impl BroadcastTransmitter {
+    pub fn new(capacity: usize) -> Result<Self, Error> {
+        if !is_capacity_valid(capacity, BROADCAST_BUFFER_MIN_CAPACITY) {
+            return Err(Error::InvalidCapacity(capacity));
+        }
+
+        Self::from_backing(Backing::heap(capacity + BROADCAST_BUFFER_TRAILER_LENGTH))
+    }
+
+    /// Creates a transmitter over `backing`, which must either be zeroed or already hold a
+    /// broadcast buffer whose transmitter is gone.
+    pub fn from_backing(backing: Backing) -> Result<Self, Error> {
+        let broadcast_buffer = BroadcastBuffer::from_backing(backing)?;
+
+        Ok(Self {
+            max_message_length: broadcast_buffer.capacity / 8,
+            broadcast_buffer,
+        })
+    }
+
+    /// Creates a broadcast buffer with `capacity` in a new file at `path`, replacing any existing
+    /// file.
+    ///
+    /// Other processes follow it with [`BroadcastReceiver::open_file`].
+    pub fn create_file(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        if !is_capacity_valid(capacity, BROADCAST_BUFFER_MIN_CAPACITY) {
+            return Err(Error::InvalidCapacity(capacity).into());
+        }
+
+        Ok(Self::from_backing(Backing::create_file(
+            path,
+            capacity + BROADCAST_BUFFER_TRAILER_LENGTH,
+        )?)?)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.broadcast_buffer.capacity
+    }
+
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
+
+    /// Returns a receiver that starts at the latest record.
+    pub fn receiver(&self) -> BroadcastReceiver {
+        BroadcastReceiver::new(self.broadcast_buffer.clone())
+    }
+
+    /// Writes a record to every receiver, overwriting the oldest records of the buffer.
+    pub fn transmit(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), Error> {
+        check_message(self.max_message_length, msg_type_id, msg.len())?;
+
+        let capacity = self.broadcast_buffer.capacity;
+        let descriptor = self.broadcast_buffer.descriptor();
+
+        let mut current_tail = descriptor.tail_counter.load(Ordering::Relaxed);
+        let mut record_offset = self.broadcast_buffer.record_offset(current_tail);
+        let record_length = AERON_RB_RECORD_HEADER_LENGTH + msg.len();
+        let aligned_record_length = aeron_align(record_length, AERON_RB_ALIGNMENT);
+        let new_tail = current_tail + aligned_record_length as i64;
+        let to_end_of_buffer = capacity - record_offset;
+
+        if to_end_of_buffer < aligned_record_length {
+            self.signal_tail_intent(new_tail + to_end_of_buffer as i64);
+
+            self.broadcast_buffer.store_header(
+                record_offset,
+                to_end_of_buffer as i32,
+                AERON_RB_PADDING_MSG_TYPE_ID,
+            );
+
+            current_tail += to_end_of_buffer as i64;
+            record_offset = 0;
+        } else {
+            self.signal_tail_intent(new_tail);
+        }
+
+        self.broadcast_buffer
+            .store_header(record_offset, record_length as i32, msg_type_id);
+        self.broadcast_buffer.store_message(record_offset, msg);
+
+        descriptor
+            .latest_counter
+            .store(current_tail, Ordering::Relaxed);
+        descriptor.tail_counter.store(
+            current_tail + aligned_record_length as i64,
+            Ordering::Release,
+        );
+
+        Ok(())
+    }
+
+    /// Announces the records up to `new_tail` are about to be overwritten, before any of their
+    /// bytes are.
+    fn signal_tail_intent(&self, new_tail: i64) {
+        self.broadcast_buffer
+            .descriptor()
+            .tail_intent_counter
+            .store(new_tail, Ordering::Relaxed);
+        fence(Ordering::Release);
+    }
+}
+
+/// Follows the records of a [`BroadcastTransmitter`] in place.
+///
+/// After [`BroadcastReceiver::receive_next`] the current record is read through
+/// [`BroadcastReceiver::copy_message`]. The transmitter may overwrite it at any time, so whatever was
+/// read from it only counts once [`BroadcastReceiver::validate`] confirms it wasn't lapped in the
+/// meantime. [`CopyBroadcastReceiver`] does all of that.
+#[derive(Debug)]
+pub struct BroadcastReceiver {
+    broadcast_buffer: BroadcastBuffer,
+    cursor: i64,
+    next_record: i64,
+    record_offset: usize,
+    lapped_count: u64,
+}
+
+unsafe impl Send for BroadcastReceiver {}
+
+impl BroadcastReceiver {
+    /// Follows the broadcast buffer in `backing`, starting at the latest record.
+    pub fn from_backing(backing: Backing) -> Result<Self, Error> {
+        BroadcastBuffer::from_backing(backing).map(Self::new)
+    }
+
+    /// Follows a broadcast buffer created by [`BroadcastTransmitter::create_file`].
+    pub fn open_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from_backing(Backing::open_file(path)?)?)
+    }
+
+    fn new(broadcast_buffer: BroadcastBuffer) -> Self {
+        let cursor = broadcast_buffer
+            .descriptor()
+            .latest_counter
+            .load(Ordering::Acquire);
+
+        Self {
+            record_offset: broadcast_buffer.record_offset(cursor),
+            broadcast_buffer,
+            cursor,
+            next_record: cursor,
+            lapped_count: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.broadcast_buffer.capacity
+    }
+
+    /// Number of times the transmitter overwrote records before they were read.
+    pub fn lapped_count(&self) -> u64 {
+        self.lapped_count
+    }
+
+    /// Moves to the next record, returns `false` if the transmitter hasn't written one yet.
+    pub fn receive_next(&mut self) -> bool {
+        let descriptor = self.broadcast_buffer.descriptor();
+        let tail = descriptor.tail_counter.load(Ordering::Acquire);
+        let mut cursor = self.next_record;
+
+        if tail <= cursor {
+            return false;
+        }
+
+        if !self.is_valid(cursor) {
+            self.lapped_count += 1;
+            cursor = descriptor.latest_counter.load(Ordering::Acquire);
+        }
+
+        self.cursor = cursor;
+        self.record_offset = self.broadcast_buffer.record_offset(cursor);
+        self.next_record = cursor + self.aligned_record_length();
+
+        if self.msg_type_id() == AERON_RB_PADDING_MSG_TYPE_ID {
+            self.cursor = self.next_record;
+            self.record_offset = 0;
+            self.next_record += self.aligned_record_length();
+        }
+
+        true
+    }
+
+    pub fn msg_type_id(&self) -> i32 {
+        self.broadcast_buffer.load_header(self.record_offset).1
+    }
+
+    /// Length of the message of the current record, at most up to the end of the buffer.
+    pub fn length(&self) -> usize {
+        let length = self.broadcast_buffer.load_header(self.record_offset).0 as usize;
+        let max_length = self.broadcast_buffer.capacity - self.record_offset;
+
+        length.clamp(AERON_RB_RECORD_HEADER_LENGTH, max_length) - AERON_RB_RECORD_HEADER_LENGTH
+    }
+
+    /// Copies the start of the message of the current record into `dst`, which must not be longer
+    /// than [`Self::length`].
+    ///
+    /// The transmitter may be overwriting the record meanwhile, the copy is only what it wrote if
+    /// [`Self::validate`] says so afterwards.
+    pub fn copy_message(&self, dst: &mut [u8]) {
+        self.broadcast_buffer.load_message(self.record_offset, dst);
+    }
+
+    /// Returns `true` if the current record hasn't been overwritten, so whatever was read from it
+    /// is what the transmitter wrote.
+    pub fn validate(&self) -> bool {
+        fence(Ordering::Acquire);
+        self.is_valid(self.cursor)
+    }
+
+    fn is_valid(&self, cursor: i64) -> bool {
+        cursor + self.broadcast_buffer.capacity as i64
+            > self
+                .broadcast_buffer
+                .descriptor()
+                .tail_intent_counter
+                .load(Ordering::Acquire)
+    }
+
+    /// Aligned length of the record at the record offset, never less than a header so the
+    /// receiver keeps moving even over bytes that are being overwritten.
+    fn aligned_record_length(&self) -> i64 {
+        let length = self.broadcast_buffer.load_header(self.record_offset).0 as usize;
+        let max_length = self.broadcast_buffer.capacity - self.record_offset;
+
+        aeron_align(
+            length.clamp(AERON_RB_RECORD_HEADER_LENGTH, max_length),
+            AERON_RB_ALIGNMENT,
+        ) as i64
+    }
+}
+
+/// The transmitter overwrote records before the receiver read them, they are lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lapped;
+
+impl fmt::Display for Lapped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unable to keep up with the broadcast")
+    }
+}
+
+impl std::error::Error for Lapped {}
+
+/// Receiver that copies each record out of the broadcast buffer before handing it out, so the
+/// handler only ever sees whole messages.
+#[derive(Debug)]
+pub struct CopyBroadcastReceiver {
+    receiver: BroadcastReceiver,
+    scratch: Vec<u8>,
+}
+
+impl CopyBroadcastReceiver {
+    pub fn new(receiver: BroadcastReceiver) -> Self {
+        Self {
+            scratch: vec![0; receiver.capacity() / 8],
+            receiver,
+        }
+    }
+
+    pub fn lapped_count(&self) -> u64 {
+        self.receiver.lapped_count()
+    }
+
+    /// Calls `handler` with the next message, returns the number of messages received.
+    ///
+    /// Fails with [`Lapped`] when the transmitter overwrote records before they were read. Those
+    /// messages are lost, along with the latest record the receiver skipped to, the next call
+    /// continues after it.
+    pub fn receive<F>(&mut self, mut handler: F) -> Result<usize, Lapped>
+    where
+        F: FnMut(i32, &[u8]),
+    {
+        let last_seen_lapped_count = self.receiver.lapped_count();
+
+        if !self.receiver.receive_next() {
+            return Ok(0);
+        }
+
+        if self.receiver.lapped_count() != last_seen_lapped_count {
+            return Err(Lapped);
+        }
+
+        let msg_type_id = self.receiver.msg_type_id();
+        let length = self.receiver.length();
+        // Longer than the transmitter accepts, so the header was overwritten.
+        if length > self.scratch.len() {
+            return Err(Lapped);
+        }
+
+        let msg = &mut self.scratch[..length];
+        self.receiver.copy_message(msg);
+
+        if !self.receiver.validate() {
+            return Err(Lapped);
+        }
+
+        handler(msg_type_id, msg);
+
+        Ok(1)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::{BroadcastTransmitter, CopyBroadcastReceiver, Lapped};
+    use crate::error::Error;
+
+    fn receive_all(receiver: &mut CopyBroadcastReceiver) -> Result<Vec<(i32, Vec<u8>)>, Lapped> {
+        let mut received = Vec::new();
+        while receiver.receive(|msg_type_id, msg| received.push((msg_type_id, msg.to_vec())))? > 0 {
+        }
+
+        Ok(received)
+    }
+
+    #[test]
+    fn every_receiver_gets_every_message() {
+        let mut transmitter = BroadcastTransmitter::new(1024).unwrap();
+        let mut receivers = [
+            CopyBroadcastReceiver::new(transmitter.receiver()),
+            CopyBroadcastReceiver::new(transmitter.receiver()),
+        ];
+
+        transmitter.transmit(1, &[1]).unwrap();
+        transmitter.transmit(2, &[2, 2]).unwrap();
+        transmitter.transmit(3, &[]).unwrap();
+
+        for receiver in &mut receivers {
+            assert_eq!(
+                receive_all(receiver),
+                Ok(vec![(1, vec![1]), (2, vec![2, 2]), (3, vec![])])
+            );
+        }
+    }
+
+    #[test]
+    fn late_receiver_starts_at_latest_record() {
+        let mut transmitter = BroadcastTransmitter::new(1024).unwrap();
+
+        transmitter.transmit(1, &[1]).unwrap();
+        transmitter.transmit(2, &[2]).unwrap();
+        let mut receiver = CopyBroadcastReceiver::new(transmitter.receiver());
+        transmitter.transmit(3, &[3]).unwrap();
+
+        assert_eq!(
+            receive_all(&mut receiver),
+            Ok(vec![(2, vec![2]), (3, vec![3])])
+        );
+    }
+
+    #[test]
+    fn receiver_keeping_up_reads_past_the_padding() {
+        let mut transmitter = BroadcastTransmitter::new(256).unwrap();
+        let mut receiver = CopyBroadcastReceiver::new(transmitter.receiver());
+
+        // 24 bytes records don't divide the capacity, so every lap ends with padding.
+        for sequence in 0..100u8 {
+            transmitter.transmit(1, &[sequence; 16]).unwrap();
+            assert_eq!(
+                receive_all(&mut receiver),
+                Ok(vec![(1, vec![sequence; 16])])
+            );
+        }
+        assert_eq!(receiver.lapped_count(), 0);
+    }
+
+    #[test]
+    fn lapped_receiver_reports_it_and_skips_to_latest() {
+        let mut transmitter = BroadcastTransmitter::new(256).unwrap();
+        let mut receiver = CopyBroadcastReceiver::new(transmitter.receiver());
+
+        for sequence in 0..20u8 {
+            transmitter.transmit(1, &[sequence; 16]).unwrap();
+        }
+
+        assert_eq!(receive_all(&mut receiver), Err(Lapped));
+        assert_eq!(receiver.lapped_count(), 1);
+        transmitter.transmit(2, &[20; 16]).unwrap();
+        assert_eq!(receive_all(&mut receiver), Ok(vec![(2, vec![20; 16])]));
+    }
+
+    #[test]
+    fn transmit_rejects_invalid_messages() {
+        let mut transmitter = BroadcastTransmitter::new(1024).unwrap();
+
+        assert_eq!(
+            transmitter.transmit(1, &[0; 129]),
+            Err(Error::MessageTooLong {
+                length: 129,
+                max_message_length: 128
+            })
+        );
+        assert_eq!(
+            transmitter.transmit(0, &[]),
+            Err(Error::InvalidMsgTypeId(0))
+        );
+        assert!(BroadcastTransmitter::new(1000).is_err());
+    }
+
+    #[test]
+    fn receivers_only_see_whole_messages_while_transmitting() {
+        let mut transmitter = BroadcastTransmitter::new(1024).unwrap();
+        let messages: u32 = 20_000;
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                let mut receiver = CopyBroadcastReceiver::new(transmitter.receiver());
+                let done = &done;
+                s.spawn(move || {
+                    let mut last_sequence = None;
+                    loop {
+                        // Checked first so the records written before it are all received.
+                        let is_done = done.load(Ordering::Acquire);
+                        let result = receiver.receive(|_, msg| {
+                            let sequence = u32::from_le_bytes(msg[..4].try_into().unwrap());
+                            assert!(msg[4..].iter().all(|&b| b == sequence as u8));
+                            assert!(last_sequence.is_none_or(|last| sequence > last));
+                            last_sequence = Some(sequence);
+                        });
+
+                        if result == Ok(0) {
+                            if is_done {
+                                break;
+                            }
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            for sequence in 0..messages {
+                let mut msg = vec![sequence as u8; 4 + sequence as usize % 60];
+                msg[..4].copy_from_slice(&sequence.to_le_bytes());
+                transmitter.transmit(1, &msg).unwrap();
+                if sequence % 16 == 0 {
+                    std::thread::yield_now();
+                }
+            }
+            done.store(true, Ordering::Release);
+        });
+    }
+
+    #[test]
+    fn receiver_follows_another_mapping() {
+        let path = std::env::temp_dir().join(format!("agrona-broadcast-{}", std::process::id()));
+        let mut transmitter = BroadcastTransmitter::create_file(&path, 1024).unwrap();
+        transmitter.transmit(1, b"before").unwrap();
+
+        let mut receiver =
+            CopyBroadcastReceiver::new(super::BroadcastReceiver::open_file(&path).unwrap());
+        transmitter.transmit(2, b"after").unwrap();
+
+        assert_eq!(
+            receive_all(&mut receiver),
+            Ok(vec![(1, b"before".to_vec()), (2, b"after".to_vec())])
+        );
+
+        drop((transmitter, receiver));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
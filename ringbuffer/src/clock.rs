@@ -0,0 +1,54 @@
+//! Clocks, injected wherever time is compared so the logic can be tested deterministically.
+
+use std::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Milliseconds since the unix epoch.
+pub trait EpochClock {
+    fn time(&self) -> i64;
+}
+
+/// Reads the system time on every call.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemEpochClock;
+
+impl EpochClock for SystemEpochClock {
+    fn time(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is after the unix epoch")
+            .as_millis() as i64
+    }
+}
+
+/// Only changes when told to, either to cache an expensive clock or to control time in tests.
+#[derive(Debug, Default)]
+pub struct CachedEpochClock(AtomicI64);
+
+impl CachedEpochClock {
+    pub fn new(time: i64) -> Self {
+        Self(AtomicI64::new(time))
+    }
+
+    pub fn update(&self, time: i64) {
+        self.0.store(time, Ordering::Release);
+    }
+
+    pub fn advance(&self, duration: i64) {
+        self.0.fetch_add(duration, Ordering::AcqRel);
+    }
+}
+
+impl EpochClock for CachedEpochClock {
+    fn time(&self) -> i64 {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+impl<C: EpochClock + ?Sized> EpochClock for &C {
+    fn time(&self) -> i64 {
+        (**self).time()
+    }
+}
@@ -0,0 +1,881 @@
+//! Counters: named 64-bit values in shared memory, following Aeron's `aeron_counters_manager`.
+//!
+//! Counters live in two buffers. The values buffer holds one [`COUNTER_LENGTH`] record per
+//! counter, the value padded to two cache lines so updates of neighbouring counters don't contend.
+//! The metadata buffer holds one [`METADATA_LENGTH`] record per counter:
+//!
+//! ```text
+//! 0       4         8                         16            128            132
+//! +-------+---------+-------------------------+-------------+--------------+-------------
+//! | state | type id | free for reuse deadline | key ...     | label length | label ...
+//! +-------+---------+-------------------------+-------------+--------------+-------------
+//! ```
+//!
+//! A [`CountersManager`] allocates and frees counters, [`CountersReader`]s, possibly in other
+//! processes, iterate over them and read their values.
+//!
+//! Code updating a single counter holds a handle on it instead: an [`AtomicCounter`] or a
+//! [`Position`] from the manager to write it, a [`ReadablePosition`] from any reader to observe it.
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    collections::HashSet,
+    io,
+    marker::PhantomData,
+    mem::{align_of, offset_of, size_of},
+    ops::Deref,
+    path::Path,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicI32, AtomicI64, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    backing::Backing,
+    clock::{EpochClock, SystemEpochClock},
+    error::Error,
+    AERON_CACHE_LINE_LENGTH,
+};
+
+/// Length of the record of a counter in the values buffer.
+pub const COUNTER_LENGTH: usize = size_of::<CounterValue>();
+/// Length of the record of a counter in the metadata buffer.
+pub const METADATA_LENGTH: usize = size_of::<CounterMetadata>();
+pub const MAX_KEY_LENGTH: usize = 2 * AERON_CACHE_LINE_LENGTH - 16;
+pub const MAX_LABEL_LENGTH: usize = 6 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI32>();
+
+/// State of a metadata record that was never allocated, iteration stops at the first one.
+pub const RECORD_UNUSED: i32 = 0;
+pub const RECORD_ALLOCATED: i32 = 1;
+/// State of a freed counter, waiting for its free for reuse deadline.
+pub const RECORD_RECLAIMED: i32 = -1;
+
+/// Free for reuse deadline of an allocated counter.
+pub const NOT_FREE_TO_REUSE: i64 = i64::MAX;
+
+#[repr(C)]
+struct CounterValue {
+    value: AtomicI64,
+    _pad: [UnsafeCell<u8>; 2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI64>()],
+}
+
+/// Key and label are copied a word at a time with relaxed atomics, readers may copy them while the
+/// manager rewrites a reused record. The label only starts 4-byte aligned, so its words are 32-bit.
+#[repr(C)]
+struct CounterMetadata {
+    state: AtomicI32,
+    type_id: AtomicI32,
+    free_for_reuse_deadline: AtomicI64,
+    key: [AtomicU64; MAX_KEY_LENGTH / KEY_WORD_LENGTH],
+    label_length: AtomicI32,
+    label: [AtomicU32; MAX_LABEL_LENGTH / LABEL_WORD_LENGTH],
+}
+
+const KEY_WORD_LENGTH: usize = size_of::<AtomicU64>();
+const LABEL_WORD_LENGTH: usize = size_of::<AtomicU32>();
+
+const _: () = assert!(COUNTER_LENGTH == 2 * AERON_CACHE_LINE_LENGTH);
+const _: () = assert!(METADATA_LENGTH == 8 * AERON_CACHE_LINE_LENGTH);
+const _: () = assert!(MAX_KEY_LENGTH == 112 && MAX_LABEL_LENGTH == 380);
+const _: () =
+    assert!(offset_of!(CounterMetadata, key) == 16 && offset_of!(CounterMetadata, label) == 132);
+const _: () = assert!(
+    MAX_KEY_LENGTH.is_multiple_of(KEY_WORD_LENGTH)
+        && MAX_LABEL_LENGTH.is_multiple_of(LABEL_WORD_LENGTH)
+);
+
+/// Reads counters, typically those of a [`CountersManager`] in another process.
+#[derive(Debug, Clone)]
+pub struct CountersReader {
+    metadata: NonNull<CounterMetadata>,
+    values: NonNull<CounterValue>,
+    max_counter_id: i32,
+    metadata_backing: Arc<Backing>,
+    values_backing: Arc<Backing>,
+}
+
+unsafe impl Send for CountersReader {}
+unsafe impl Sync for CountersReader {}
+
+impl CountersReader {
+    /// Reads the counters in `metadata` and `values`, which must either be zeroed or hold counters.
+    ///
+    /// The metadata buffer needs a record for every counter of the values buffer.
+    pub fn new(metadata: Backing, values: Backing) -> Result<Self, Error> {
+        for backing in [&metadata, &values] {
+            if backing.as_ptr().align_offset(align_of::<CounterMetadata>()) != 0 {
+                return Err(Error::MisalignedBuffer {
+                    alignment: align_of::<CounterMetadata>(),
+                });
+            }
+        }
+
+        let max_counter_id = values.len() / COUNTER_LENGTH;
+        if max_counter_id == 0 || max_counter_id > i32::MAX as usize {
+            return Err(Error::InvalidCapacity(values.len()));
+        }
+        if metadata.len() < max_counter_id * METADATA_LENGTH {
+            return Err(Error::InvalidCapacity(metadata.len()));
+        }
+
+        Ok(Self {
+            metadata: NonNull::new(metadata.as_ptr()).unwrap().cast(),
+            values: NonNull::new(values.as_ptr()).unwrap().cast(),
+            max_counter_id: max_counter_id as i32 - 1,
+            metadata_backing: Arc::new(metadata),
+            values_backing: Arc::new(values),
+        })
+    }
+
+    /// Reads the counters in files created with [`Backing::create_file`] by a [`CountersManager`].
+    pub fn open_files(
+        metadata_path: impl AsRef<Path>,
+        values_path: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        Ok(Self::new(
+            Backing::open_file(metadata_path)?,
+            Backing::open_file(values_path)?,
+        )?)
+    }
+
+    /// Largest counter id the buffers have room for.
+    pub fn max_counter_id(&self) -> i32 {
+        self.max_counter_id
+    }
+
+    /// Calls `handler` with the id, type id, key and label of every allocated counter.
+    pub fn for_each<F>(&self, mut handler: F)
+    where
+        F: FnMut(i32, i32, &[u8; MAX_KEY_LENGTH], &str),
+    {
+        for counter_id in 0..=self.max_counter_id {
+            match self.counter_state(counter_id) {
+                Some(RECORD_UNUSED) | None => break,
+                Some(RECORD_ALLOCATED) => handler(
+                    counter_id,
+                    self.metadata_at(counter_id).type_id.load(Ordering::Relaxed),
+                    &self.key_at(counter_id),
+                    &self.label_at(counter_id),
+                ),
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Value of a counter, `None` if `counter_id` is beyond [`CountersReader::max_counter_id`].
+    pub fn counter_value(&self, counter_id: i32) -> Option<i64> {
+        self.value(counter_id)
+            .map(|value| value.load(Ordering::Acquire))
+    }
+
+    /// One of [`RECORD_UNUSED`], [`RECORD_ALLOCATED`] or [`RECORD_RECLAIMED`].
+    pub fn counter_state(&self, counter_id: i32) -> Option<i32> {
+        self.metadata(counter_id)
+            .map(|metadata| metadata.state.load(Ordering::Acquire))
+    }
+
+    pub fn counter_type_id(&self, counter_id: i32) -> Option<i32> {
+        self.metadata(counter_id)
+            .map(|metadata| metadata.type_id.load(Ordering::Relaxed))
+    }
+
+    /// Time, in milliseconds since the epoch, from which a freed counter may be allocated again.
+    pub fn free_for_reuse_deadline(&self, counter_id: i32) -> Option<i64> {
+        self.metadata(counter_id)
+            .map(|metadata| metadata.free_for_reuse_deadline.load(Ordering::Relaxed))
+    }
+
+    pub fn counter_key(&self, counter_id: i32) -> Option<[u8; MAX_KEY_LENGTH]> {
+        self.contains(counter_id).then(|| self.key_at(counter_id))
+    }
+
+    pub fn counter_label(&self, counter_id: i32) -> Option<String> {
+        self.contains(counter_id).then(|| self.label_at(counter_id))
+    }
+
+    /// Read-only handle on the value of a counter.
+    pub fn readable_position(&self, counter_id: i32) -> Option<ReadablePosition> {
+        self.slot(counter_id).map(ReadablePosition)
+    }
+
+    fn contains(&self, counter_id: i32) -> bool {
+        (0..=self.max_counter_id).contains(&counter_id)
+    }
+
+    fn slot(&self, counter_id: i32) -> Option<CounterSlot> {
+        self.contains(counter_id).then(|| self.slot_at(counter_id))
+    }
+
+    fn value(&self, counter_id: i32) -> Option<&AtomicI64> {
+        self.contains(counter_id).then(|| self.value_at(counter_id))
+    }
+
+    fn metadata(&self, counter_id: i32) -> Option<&CounterMetadata> {
+        self.contains(counter_id)
+            .then(|| self.metadata_at(counter_id))
+    }
+
+    /// For ids known to be in range, panics otherwise.
+    fn slot_at(&self, counter_id: i32) -> CounterSlot {
+        CounterSlot {
+            value: NonNull::from(self.value_at(counter_id)),
+            counter_id,
+            _values_backing: self.values_backing.clone(),
+        }
+    }
+
+    /// For ids known to be in range, panics otherwise.
+    fn value_at(&self, counter_id: i32) -> &AtomicI64 {
+        assert!(self.contains(counter_id));
+        unsafe { &self.values.add(counter_id as usize).as_ref().value }
+    }
+
+    /// For ids known to be in range, panics otherwise.
+    fn metadata_at(&self, counter_id: i32) -> &CounterMetadata {
+        assert!(self.contains(counter_id));
+        unsafe { self.metadata.add(counter_id as usize).as_ref() }
+    }
+
+    fn key_at(&self, counter_id: i32) -> [u8; MAX_KEY_LENGTH] {
+        let mut key = [0; MAX_KEY_LENGTH];
+        for (chunk, word) in key
+            .chunks_exact_mut(KEY_WORD_LENGTH)
+            .zip(&self.metadata_at(counter_id).key)
+        {
+            chunk.copy_from_slice(&word.load(Ordering::Relaxed).to_ne_bytes());
+        }
+
+        key
+    }
+
+    fn label_at(&self, counter_id: i32) -> String {
+        let metadata = self.metadata_at(counter_id);
+        let length = (metadata.label_length.load(Ordering::Relaxed) as usize).min(MAX_LABEL_LENGTH);
+
+        let mut label = vec![0; length];
+        for (chunk, word) in label.chunks_mut(LABEL_WORD_LENGTH).zip(&metadata.label) {
+            chunk.copy_from_slice(&word.load(Ordering::Relaxed).to_ne_bytes()[..chunk.len()]);
+        }
+
+        String::from_utf8_lossy(&label).into_owned()
+    }
+}
+
+/// Allocates and frees the counters of a metadata and a values buffer.
+///
+/// There must be only one manager per buffers, the [`CountersReader`] it dereferences to reads its
+/// counters in the same process.
+#[derive(Debug)]
+pub struct CountersManager<C: EpochClock = SystemEpochClock> {
+    reader: CountersReader,
+    clock: C,
+    free_to_reuse_timeout_ms: i64,
+    id_high_water_mark: i32,
+    free_list: Vec<i32>,
+    /// Counters written by a [`Position`], which no other handle may write.
+    position_ids: HashSet<i32>,
+}
+
+impl<C: EpochClock> CountersManager<C> {
+    /// Manages the counters in `metadata` and `values`, which must be zeroed.
+    ///
+    /// Freed counters are only allocated again `free_to_reuse_timeout_ms` after they were freed,
+    /// so readers have time to notice.
+    pub fn new(
+        metadata: Backing,
+        values: Backing,
+        clock: C,
+        free_to_reuse_timeout_ms: i64,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            reader: CountersReader::new(metadata, values)?,
+            clock,
+            free_to_reuse_timeout_ms,
+            id_high_water_mark: -1,
+            free_list: Vec::new(),
+            position_ids: HashSet::new(),
+        })
+    }
+
+    /// Allocates a counter and returns its id.
+    ///
+    /// `key` is copied into a key of [`MAX_KEY_LENGTH`] bytes, padded with zeroes, `label` is
+    /// truncated to [`MAX_LABEL_LENGTH`] bytes.
+    pub fn allocate(&mut self, type_id: i32, key: &[u8], label: &str) -> Result<i32, Error> {
+        if key.len() > MAX_KEY_LENGTH {
+            return Err(Error::MessageTooLong {
+                length: key.len(),
+                max_message_length: MAX_KEY_LENGTH,
+            });
+        }
+
+        let counter_id = self.next_counter_id()?;
+        let metadata = self.reader.metadata_at(counter_id);
+
+        let mut label_length = label.len().min(MAX_LABEL_LENGTH);
+        while !label.is_char_boundary(label_length) {
+            label_length -= 1;
+        }
+
+        let mut padded_key = [0; MAX_KEY_LENGTH];
+        padded_key[..key.len()].copy_from_slice(key);
+        for (word, chunk) in metadata
+            .key
+            .iter()
+            .zip(padded_key.chunks_exact(KEY_WORD_LENGTH))
+        {
+            word.store(
+                u64::from_ne_bytes(chunk.try_into().unwrap()),
+                Ordering::Relaxed,
+            );
+        }
+
+        for (word, chunk) in metadata
+            .label
+            .iter()
+            .zip(label.as_bytes()[..label_length].chunks(LABEL_WORD_LENGTH))
+        {
+            let mut bytes = [0; LABEL_WORD_LENGTH];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            word.store(u32::from_ne_bytes(bytes), Ordering::Relaxed);
+        }
+        metadata
+            .label_length
+            .store(label_length as i32, Ordering::Relaxed);
+        metadata.type_id.store(type_id, Ordering::Relaxed);
+        metadata
+            .free_for_reuse_deadline
+            .store(NOT_FREE_TO_REUSE, Ordering::Relaxed);
+        // Readers acquire the state before reading the rest of the record.
+        metadata.state.store(RECORD_ALLOCATED, Ordering::Release);
+
+        Ok(counter_id)
+    }
+
+    /// Frees an allocated counter, its id is reused once the free to reuse timeout has elapsed.
+    ///
+    /// Returns `false`, freeing nothing, if `counter_id` is not an allocated counter.
+    pub fn free(&mut self, counter_id: i32) -> bool {
+        let Some(metadata) = self.reader.metadata(counter_id) else {
+            return false;
+        };
+        if metadata.state.load(Ordering::Relaxed) != RECORD_ALLOCATED {
+            return false;
+        }
+
+        metadata.free_for_reuse_deadline.store(
+            self.clock.time() + self.free_to_reuse_timeout_ms,
+            Ordering::Relaxed,
+        );
+        metadata.state.store(RECORD_RECLAIMED, Ordering::Release);
+        self.free_list.push(counter_id);
+        self.position_ids.remove(&counter_id);
+
+        true
+    }
+
+    /// Allocates a counter, see [`CountersManager::allocate`], and returns a handle on it.
+    pub fn new_counter(
+        &mut self,
+        type_id: i32,
+        key: &[u8],
+        label: &str,
+    ) -> Result<AtomicCounter, Error> {
+        let counter_id = self.allocate(type_id, key, label)?;
+
+        Ok(AtomicCounter(self.reader.slot_at(counter_id)))
+    }
+
+    /// Allocates a counter, see [`CountersManager::allocate`], and returns the only [`Position`]
+    /// on it.
+    pub fn new_position(
+        &mut self,
+        type_id: i32,
+        key: &[u8],
+        label: &str,
+    ) -> Result<Position, Error> {
+        let counter_id = self.allocate(type_id, key, label)?;
+        self.position_ids.insert(counter_id);
+
+        Ok(Position::new(self.reader.slot_at(counter_id)))
+    }
+
+    /// Handle on an allocated counter that any number of threads may update, `None` if
+    /// `counter_id` is not allocated or written by a [`Position`].
+    pub fn atomic_counter(&self, counter_id: i32) -> Option<AtomicCounter> {
+        (self.reader.counter_state(counter_id)? == RECORD_ALLOCATED
+            && !self.position_ids.contains(&counter_id))
+        .then(|| AtomicCounter(self.reader.slot_at(counter_id)))
+    }
+
+    /// Returns `false`, setting nothing, if `counter_id` is beyond
+    /// [`CountersReader::max_counter_id`] or written by a [`Position`].
+    pub fn set_counter_value(&self, counter_id: i32, value: i64) -> bool {
+        let Some(counter) = self.reader.value(counter_id) else {
+            return false;
+        };
+        if self.position_ids.contains(&counter_id) {
+            return false;
+        }
+        counter.store(value, Ordering::Release);
+
+        true
+    }
+
+    fn next_counter_id(&mut self) -> Result<i32, Error> {
+        let now_ms = self.clock.time();
+
+        if let Some(index) = self.free_list.iter().position(|&counter_id| {
+            now_ms
+                >= self
+                    .reader
+                    .metadata_at(counter_id)
+                    .free_for_reuse_deadline
+                    .load(Ordering::Relaxed)
+        }) {
+            let counter_id = self.free_list.remove(index);
+            self.reader.value_at(counter_id).store(0, Ordering::Release);
+            return Ok(counter_id);
+        }
+
+        if self.id_high_water_mark == self.reader.max_counter_id {
+            return Err(Error::InsufficientCapacity);
+        }
+
+        self.id_high_water_mark += 1;
+        Ok(self.id_high_water_mark)
+    }
+}
+
+impl<C: EpochClock> Deref for CountersManager<C> {
+    type Target = CountersReader;
+
+    fn deref(&self) -> &Self::Target {
+        &self.reader
+    }
+}
+
+/// The value of a counter, kept mapped for as long as a handle on it is alive.
+#[derive(Debug, Clone)]
+struct CounterSlot {
+    value: NonNull<AtomicI64>,
+    counter_id: i32,
+    _values_backing: Arc<Backing>,
+}
+
+unsafe impl Send for CounterSlot {}
+unsafe impl Sync for CounterSlot {}
+
+impl CounterSlot {
+    fn value(&self) -> &AtomicI64 {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+/// Read-write handle on a counter, updated atomically so it can be shared by any number of
+/// threads.
+#[derive(Debug, Clone)]
+pub struct AtomicCounter(CounterSlot);
+
+impl AtomicCounter {
+    pub fn id(&self) -> i32 {
+        self.0.counter_id
+    }
+
+    /// Adds one and returns the previous value.
+    pub fn increment(&self) -> i64 {
+        self.get_and_add(1)
+    }
+
+    /// Adds `delta` and returns the previous value.
+    pub fn get_and_add(&self, delta: i64) -> i64 {
+        self.0.value().fetch_add(delta, Ordering::AcqRel)
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.value().load(Ordering::Acquire)
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.value().store(value, Ordering::SeqCst);
+    }
+
+    /// Stores `value` with release ordering, readers that see it also see what came before.
+    pub fn set_ordered(&self, value: i64) {
+        self.0.value().store(value, Ordering::Release);
+    }
+
+    /// Raises the value to `value` if it is higher, returns `true` if it was.
+    pub fn propose_max(&self, value: i64) -> bool {
+        self.0.value().fetch_max(value, Ordering::AcqRel) < value
+    }
+}
+
+/// Read-write handle on a counter with a single writer, such as a stream position.
+///
+/// Only [`CountersManager::new_position`] creates one, along with its counter, and it is neither
+/// `Clone` nor `Sync`: [`Position::propose_max`] reads then writes without a compare and swap,
+/// which is only sound while this handle is the one writer. The manager refuses to write the
+/// counter or hand out an [`AtomicCounter`] on it until it is freed, every other party observes it
+/// through a [`ReadablePosition`].
+///
+/// ```compile_fail
+/// # use agrona::{backing::Backing, clock::SystemEpochClock, counters::*};
+/// let mut manager = CountersManager::new(
+///     Backing::heap(METADATA_LENGTH),
+///     Backing::heap(COUNTER_LENGTH),
+///     SystemEpochClock,
+///     0,
+/// )
+/// .unwrap();
+/// let position = manager.new_position(1, &[], "position").unwrap();
+/// std::thread::scope(|s| {
+///     s.spawn(|| position.propose_max(1));
+/// });
+/// ```
+#[derive(Debug)]
+pub struct Position {
+    slot: CounterSlot,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl Position {
+    fn new(slot: CounterSlot) -> Self {
+        Self {
+            slot,
+            _not_sync: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.slot.counter_id
+    }
+
+    /// Value last set by this handle, no other writer can have changed it.
+    pub fn get(&self) -> i64 {
+        self.slot.value().load(Ordering::Relaxed)
+    }
+
+    pub fn get_volatile(&self) -> i64 {
+        self.slot.value().load(Ordering::Acquire)
+    }
+
+    pub fn set(&self, value: i64) {
+        self.slot.value().store(value, Ordering::Relaxed);
+    }
+
+    /// Stores `value` with release ordering, readers that see it also see what came before.
+    pub fn set_ordered(&self, value: i64) {
+        self.slot.value().store(value, Ordering::Release);
+    }
+
+    /// Raises the position to `value` if it is higher, returns `true` if it was.
+    pub fn propose_max(&self, value: i64) -> bool {
+        let raised = self.get() < value;
+        if raised {
+            self.set(value);
+        }
+
+        raised
+    }
+
+    /// Like [`Position::propose_max`], storing with release ordering.
+    pub fn propose_max_ordered(&self, value: i64) -> bool {
+        let raised = self.get() < value;
+        if raised {
+            self.set_ordered(value);
+        }
+
+        raised
+    }
+}
+
+/// Read-only handle on a counter.
+///
+/// It has no way to change the value:
+///
+/// ```compile_fail
+/// # use agrona::{backing::Backing, counters::*};
+/// let reader = CountersReader::new(
+///     Backing::heap(METADATA_LENGTH),
+///     Backing::heap(COUNTER_LENGTH),
+/// )
+/// .unwrap();
+/// reader.readable_position(0).unwrap().set_ordered(1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReadablePosition(CounterSlot);
+
+impl ReadablePosition {
+    pub fn id(&self) -> i32 {
+        self.0.counter_id
+    }
+
+    pub fn get_volatile(&self) -> i64 {
+        self.0.value().load(Ordering::Acquire)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{
+        CountersManager, CountersReader, COUNTER_LENGTH, MAX_KEY_LENGTH, MAX_LABEL_LENGTH,
+        METADATA_LENGTH, RECORD_ALLOCATED, RECORD_RECLAIMED, RECORD_UNUSED,
+    };
+    use crate::{backing::Backing, clock::CachedEpochClock, error::Error};
+
+    const COUNTERS: usize = 4;
+
+    fn manager(clock: &CachedEpochClock) -> CountersManager<&CachedEpochClock> {
+        CountersManager::new(
+            Backing::heap(COUNTERS * METADATA_LENGTH),
+            Backing::heap(COUNTERS * COUNTER_LENGTH),
+            clock,
+            1_000,
+        )
+        .unwrap()
+    }
+
+    fn allocated(reader: &CountersReader) -> Vec<(i32, i32, Vec<u8>, String)> {
+        let mut counters = Vec::new();
+        reader.for_each(|counter_id, type_id, key, label| {
+            counters.push((counter_id, type_id, key[..4].to_vec(), label.to_owned()))
+        });
+        counters
+    }
+
+    #[test]
+    fn allocated_counters_are_iterated_in_order() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+
+        assert_eq!(manager.allocate(10, &[1, 2, 3, 4], "first"), Ok(0));
+        assert_eq!(manager.allocate(11, &[5], "second"), Ok(1));
+        assert!(manager.set_counter_value(1, 42));
+
+        assert_eq!(
+            allocated(&manager),
+            [
+                (0, 10, vec![1, 2, 3, 4], "first".to_owned()),
+                (1, 11, vec![5, 0, 0, 0], "second".to_owned())
+            ]
+        );
+        assert_eq!(manager.counter_value(0), Some(0));
+        assert_eq!(manager.counter_value(1), Some(42));
+        assert_eq!(manager.counter_state(2), Some(RECORD_UNUSED));
+    }
+
+    #[test]
+    fn freed_counter_is_reused_after_timeout() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+
+        let counter_id = manager.allocate(1, &[], "reused").unwrap();
+        assert!(manager.set_counter_value(counter_id, 7));
+        assert!(manager.free(counter_id));
+        assert!(!manager.free(counter_id));
+
+        assert_eq!(manager.counter_state(counter_id), Some(RECORD_RECLAIMED));
+        assert_eq!(manager.free_for_reuse_deadline(counter_id), Some(1_000));
+        assert!(allocated(&manager).is_empty());
+
+        clock.advance(999);
+        assert_eq!(manager.allocate(1, &[], "other"), Ok(1));
+
+        clock.advance(1);
+        assert_eq!(manager.allocate(1, &[], "again"), Ok(counter_id));
+        assert_eq!(manager.counter_state(counter_id), Some(RECORD_ALLOCATED));
+        assert_eq!(manager.counter_value(counter_id), Some(0));
+    }
+
+    #[test]
+    fn allocate_fails_when_full_or_key_too_long() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+
+        assert_eq!(
+            manager.allocate(1, &[0; MAX_KEY_LENGTH + 1], ""),
+            Err(Error::MessageTooLong {
+                length: MAX_KEY_LENGTH + 1,
+                max_message_length: MAX_KEY_LENGTH
+            })
+        );
+
+        for counter_id in 0..COUNTERS as i32 {
+            assert_eq!(manager.allocate(1, &[], ""), Ok(counter_id));
+        }
+        assert_eq!(
+            manager.allocate(1, &[], ""),
+            Err(Error::InsufficientCapacity)
+        );
+    }
+
+    #[test]
+    fn long_label_is_truncated_on_a_char_boundary() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+
+        let label = "é".repeat(MAX_LABEL_LENGTH);
+        let counter_id = manager.allocate(1, &[], &label).unwrap();
+
+        assert_eq!(
+            manager.counter_label(counter_id),
+            Some("é".repeat(MAX_LABEL_LENGTH / 2))
+        );
+    }
+
+    #[test]
+    fn reader_sees_counters_through_another_mapping() {
+        let dir = std::env::temp_dir();
+        let metadata_path = dir.join(format!("agrona-counters-metadata-{}", std::process::id()));
+        let values_path = dir.join(format!("agrona-counters-values-{}", std::process::id()));
+
+        let clock = CachedEpochClock::new(0);
+        let mut manager = CountersManager::new(
+            Backing::create_file(&metadata_path, COUNTERS * METADATA_LENGTH).unwrap(),
+            Backing::create_file(&values_path, COUNTERS * COUNTER_LENGTH).unwrap(),
+            &clock,
+            1_000,
+        )
+        .unwrap();
+        let reader = CountersReader::open_files(&metadata_path, &values_path).unwrap();
+
+        let counter_id = manager.allocate(3, b"key", "bytes sent").unwrap();
+        assert!(manager.set_counter_value(counter_id, 1_024));
+
+        assert_eq!(
+            allocated(&reader),
+            [(0, 3, b"key\0".to_vec(), "bytes sent".to_owned())]
+        );
+        assert_eq!(reader.counter_value(counter_id), Some(1_024));
+        assert_eq!(reader.max_counter_id(), COUNTERS as i32 - 1);
+
+        drop((manager, reader));
+        std::fs::remove_file(&metadata_path).unwrap();
+        std::fs::remove_file(&values_path).unwrap();
+    }
+
+    #[test]
+    fn atomic_counter_updates_are_seen_by_readers() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+
+        let counter = manager.new_counter(1, &[], "messages").unwrap();
+        let readable = manager.readable_position(counter.id()).unwrap();
+
+        assert_eq!(counter.increment(), 0);
+        assert_eq!(counter.get_and_add(10), 1);
+        assert_eq!(readable.get_volatile(), 11);
+
+        assert!(counter.propose_max(20));
+        assert!(!counter.propose_max(15));
+        assert_eq!(counter.get(), 20);
+
+        counter.set_ordered(3);
+        assert_eq!(manager.counter_value(counter.id()), Some(3));
+    }
+
+    #[test]
+    fn position_only_moves_forward_on_propose_max() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+
+        let position = manager.new_position(2, &[], "position").unwrap();
+        let readable = manager.readable_position(position.id()).unwrap();
+
+        position.set_ordered(100);
+        assert!(!position.propose_max(50));
+        assert!(position.propose_max_ordered(150));
+
+        assert_eq!(position.get(), 150);
+        assert_eq!(readable.get_volatile(), 150);
+    }
+
+    #[test]
+    fn position_is_the_only_writer_of_its_counter() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+
+        let position = manager.new_position(2, &[], "position").unwrap();
+        position.set(10);
+
+        assert!(manager.atomic_counter(position.id()).is_none());
+        assert!(!manager.set_counter_value(position.id(), 20));
+        assert_eq!(manager.counter_value(position.id()), Some(10));
+
+        assert!(manager.free(position.id()));
+        clock.advance(1_000);
+        let counter = manager.new_counter(1, &[], "reused").unwrap();
+        assert_eq!(counter.id(), position.id());
+        assert!(manager.atomic_counter(counter.id()).is_some());
+        assert!(manager.set_counter_value(counter.id(), 30));
+    }
+
+    #[test]
+    fn atomic_counter_clones_count_concurrently() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+        let counter = manager.new_counter(1, &[], "shared").unwrap();
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                let counter = counter.clone();
+                s.spawn(move || {
+                    for _ in 0..1_000 {
+                        counter.increment();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(counter.get(), 4_000);
+    }
+
+    #[test]
+    fn handle_outlives_manager() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+        let counter = manager.new_counter(1, &[], "outlives").unwrap();
+        drop(manager);
+
+        counter.increment();
+        assert_eq!(counter.get(), 1);
+    }
+
+    #[test]
+    fn out_of_range_or_unallocated_ids_get_nothing() {
+        let clock = CachedEpochClock::new(0);
+        let mut manager = manager(&clock);
+        let counter = manager.new_counter(1, &[], "allocated").unwrap();
+
+        for counter_id in [-1, COUNTERS as i32] {
+            assert_eq!(manager.counter_value(counter_id), None);
+            assert_eq!(manager.counter_state(counter_id), None);
+            assert_eq!(manager.counter_type_id(counter_id), None);
+            assert_eq!(manager.free_for_reuse_deadline(counter_id), None);
+            assert_eq!(manager.counter_key(counter_id), None);
+            assert_eq!(manager.counter_label(counter_id), None);
+            assert!(manager.readable_position(counter_id).is_none());
+            assert!(!manager.set_counter_value(counter_id, 1));
+            assert!(!manager.free(counter_id));
+        }
+
+        assert!(manager.atomic_counter(counter.id() + 1).is_none());
+        assert_eq!(manager.atomic_counter(counter.id()).unwrap().increment(), 0);
+        assert_eq!(counter.get(), 1);
+    }
+
+    #[test]
+    fn reader_rejects_too_small_metadata() {
+        assert_eq!(
+            CountersReader::new(
+                Backing::heap(METADATA_LENGTH),
+                Backing::heap(2 * COUNTER_LENGTH)
+            )
+            .unwrap_err(),
+            Error::InvalidCapacity(METADATA_LENGTH)
+        );
+    }
+}
@@ -1,5 +1,3 @@
-#![allow(deprecated)]
-
 //! Descriptor.
 //!
 //! The descriptor contains the following fields:
@@ -14,15 +12,23 @@ use std::{
     cell::UnsafeCell,
     mem::{align_of, size_of},
     ptr::NonNull,
-    sync::atomic::{AtomicI64, Ordering},
 };
 
-use crate::AERON_CACHE_LINE_LENGTH;
+#[cfg(loom)]
+use std::ptr::addr_of_mut;
+
+use crate::{
+    sync::{AtomicI64, AtomicU32, Ordering},
+    wakers::Wakers,
+    AERON_CACHE_LINE_LENGTH,
+};
 
 #[derive(Debug)]
 #[repr(C, align(4))]
 pub(crate) struct RawDescriptor {
-    _begin_pad: [UnsafeCell<u8>; 2 * AERON_CACHE_LINE_LENGTH],
+    /// Futex word the receiver parks on, in the otherwise unused padding in front of the tail.
+    pub consumer_parked: AtomicU32,
+    _begin_pad: [UnsafeCell<u8>; 2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicU32>()],
     pub tail_position: AtomicI64,
     // pub tail_position: i64,
     _tail_pad: [UnsafeCell<u8>; 2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI64>()],
@@ -41,6 +47,46 @@ pub(crate) struct RawDescriptor {
     _consumer_heartbeat_pad: [UnsafeCell<u8>; 2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI64>()],
 }
 
+/// A zeroed descriptor, shared by the const and the loom constructor.
+macro_rules! zeroed_raw_descriptor {
+    () => {
+        RawDescriptor {
+            consumer_parked: AtomicU32::new(0),
+            _begin_pad: [const { UnsafeCell::new(0) };
+                2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicU32>()],
+            tail_position: AtomicI64::new(0),
+            _tail_pad: [const { UnsafeCell::new(0) };
+                2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI64>()],
+            head_cache_position: AtomicI64::new(0),
+            _head_cache_pad: [const { UnsafeCell::new(0) };
+                2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI64>()],
+            head_position: AtomicI64::new(0),
+            _head_pad: [const { UnsafeCell::new(0) };
+                2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI64>()],
+            correlation_counter: AtomicI64::new(0),
+            _correlation_counter_pad: [const { UnsafeCell::new(0) };
+                2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI64>()],
+            consumer_heartbeat: AtomicI64::new(0),
+            _consumer_heartbeat_pad: [const { UnsafeCell::new(0) };
+                2 * AERON_CACHE_LINE_LENGTH - size_of::<AtomicI64>()],
+        }
+    };
+}
+
+impl RawDescriptor {
+    /// A zeroed descriptor, which is how a new ring buffer starts.
+    #[cfg(not(loom))]
+    pub(crate) const fn new() -> Self {
+        zeroed_raw_descriptor!()
+    }
+
+    /// Loom atomics can't be constructed in a const context.
+    #[cfg(loom)]
+    pub(crate) fn new() -> Self {
+        zeroed_raw_descriptor!()
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Descriptor(NonNull<RawDescriptor>);
 
@@ -51,18 +97,29 @@ impl Descriptor {
     }
 
     pub fn reset(&self) {
-        self.tail_position()
-            .store(0, std::sync::atomic::Ordering::Relaxed);
-        self.head_cache_position()
-            .store(0, std::sync::atomic::Ordering::Relaxed);
-        self.head_position()
-            .store(0, std::sync::atomic::Ordering::Relaxed);
-        self.correlation_counter()
-            .store(0, std::sync::atomic::Ordering::Relaxed);
-        self.consumer_heartbeat()
-            .store(0, std::sync::atomic::Ordering::Release);
+        // Loom atomics have to be constructed before use, the memory only holds zeroes.
+        #[cfg(loom)]
+        unsafe {
+            let raw = self.0.as_ptr();
+            addr_of_mut!((*raw).consumer_parked).write(AtomicU32::new(0));
+            addr_of_mut!((*raw).tail_position).write(AtomicI64::new(0));
+            addr_of_mut!((*raw).head_cache_position).write(AtomicI64::new(0));
+            addr_of_mut!((*raw).head_position).write(AtomicI64::new(0));
+            addr_of_mut!((*raw).correlation_counter).write(AtomicI64::new(0));
+            addr_of_mut!((*raw).consumer_heartbeat).write(AtomicI64::new(0));
+        }
+
+        self.consumer_parked().store(0, Ordering::Relaxed);
+        self.tail_position().store(0, Ordering::Relaxed);
+        self.head_cache_position().store(0, Ordering::Relaxed);
+        self.head_position().store(0, Ordering::Relaxed);
+        self.correlation_counter().store(0, Ordering::Relaxed);
+        self.consumer_heartbeat().store(0, Ordering::Release);
     }
 
+    pub fn consumer_parked(&self) -> &AtomicU32 {
+        &self.raw_descriptor().consumer_parked
+    }
     pub fn tail_position(&self) -> &AtomicI64 {
         &self.raw_descriptor().tail_position
     }
@@ -90,57 +147,102 @@ impl std::fmt::Debug for Descriptor {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct SenderDescriptor {
     pub tail_position: ReadWriteTail,
     pub head_cache_position: ReadWriteHeadCache,
     pub head_position: ReadOnlyHead,
+    pub correlation_counter: ReadWriteCorrelationCounter,
+    pub consumer_heartbeat: ReadOnlyConsumerHeartbeat,
+    pub consumer_parked: ReadWriteConsumerParked,
+    wakers: *const Wakers,
 }
 
 unsafe impl Send for SenderDescriptor {}
 
-impl From<Descriptor> for SenderDescriptor {
-    fn from(descriptor: Descriptor) -> Self {
+impl SenderDescriptor {
+    /// `wakers` must outlive the descriptor, like the memory `descriptor` points to.
+    pub fn new(descriptor: Descriptor, wakers: &Wakers) -> Self {
         Self {
             tail_position: ReadWriteTail(descriptor.tail_position()),
             head_cache_position: ReadWriteHeadCache(descriptor.head_cache_position()),
             head_position: ReadOnlyHead(descriptor.head_position()),
+            correlation_counter: ReadWriteCorrelationCounter(descriptor.correlation_counter()),
+            consumer_heartbeat: ReadOnlyConsumerHeartbeat(descriptor.consumer_heartbeat()),
+            consumer_parked: ReadWriteConsumerParked(descriptor.consumer_parked()),
+            wakers,
         }
     }
+
+    pub fn wakers(&self) -> &Wakers {
+        unsafe { &*self.wakers }
+    }
 }
 
 pub(crate) struct ReceiverDescriptor {
     pub tail_position: ReadOnlyTail,
     pub head_cache_position: ReadOnlyHeadCache,
     pub head_position: ReadWriteHead,
+    pub correlation_counter: ReadWriteCorrelationCounter,
+    pub consumer_heartbeat: ReadWriteConsumerHeartbeat,
+    pub consumer_parked: ReadWriteConsumerParked,
+    wakers: *const Wakers,
 }
 
 unsafe impl Send for ReceiverDescriptor {}
 
-impl From<Descriptor> for ReceiverDescriptor {
-    fn from(descriptor: Descriptor) -> Self {
+impl ReceiverDescriptor {
+    pub fn new(descriptor: Descriptor, wakers: &Wakers) -> Self {
         Self {
             tail_position: ReadOnlyTail(descriptor.tail_position()),
             head_cache_position: ReadOnlyHeadCache(descriptor.head_cache_position()),
             head_position: ReadWriteHead(descriptor.head_position()),
+            correlation_counter: ReadWriteCorrelationCounter(descriptor.correlation_counter()),
+            consumer_heartbeat: ReadWriteConsumerHeartbeat(descriptor.consumer_heartbeat()),
+            consumer_parked: ReadWriteConsumerParked(descriptor.consumer_parked()),
+            wakers,
         }
     }
+
+    pub fn wakers(&self) -> &Wakers {
+        unsafe { &*self.wakers }
+    }
 }
 
 pub type Head = i64;
 pub type AtomicHead = AtomicI64;
 pub struct ReadWriteHead(*const AtomicHead);
+#[derive(Clone)]
 pub struct ReadOnlyHead(*const AtomicHead);
 
 pub type HeadCache = i64;
 pub type AtomicHeadCache = AtomicI64;
+#[derive(Clone)]
 pub struct ReadWriteHeadCache(*const AtomicHeadCache);
 pub struct ReadOnlyHeadCache(*const AtomicHeadCache);
 
 pub type Tail = i64;
 pub type AtomicTail = AtomicI64;
+#[derive(Clone)]
 pub struct ReadWriteTail(*const AtomicTail);
 pub struct ReadOnlyTail(*const AtomicTail);
 
+pub type CorrelationCounter = i64;
+pub type AtomicCorrelationCounter = AtomicI64;
+#[derive(Clone)]
+pub struct ReadWriteCorrelationCounter(*const AtomicCorrelationCounter);
+
+pub type ConsumerHeartbeat = i64;
+pub type AtomicConsumerHeartbeat = AtomicI64;
+pub struct ReadWriteConsumerHeartbeat(*const AtomicConsumerHeartbeat);
+#[derive(Clone)]
+pub struct ReadOnlyConsumerHeartbeat(*const AtomicConsumerHeartbeat);
+
+pub type ConsumerParked = u32;
+pub type AtomicConsumerParked = AtomicU32;
+#[derive(Clone)]
+pub struct ReadWriteConsumerParked(*const AtomicConsumerParked);
+
 impl ReadWriteHead {
     pub fn new(ptr: *const AtomicHead) -> Self {
         Self(ptr)
@@ -150,9 +252,7 @@ impl ReadWriteHead {
 
     #[cfg(not(debug_assertions))]
     pub fn store_atomic(&self, val: Head, ord: Ordering) {
-        let atomic = unsafe {
-            let atomic = &*self.0;
-        };
+        let atomic = unsafe { &*self.0 };
 
         atomic.store(val, ord);
     }
@@ -192,21 +292,13 @@ impl ReadWriteHeadCache {
     }
 
     // IDEA: does #[inline(always)] make a difference in benchmarks?
-    #[cfg(not(debug_assertions))]
+    // Not monotonic: concurrent producers may store the head they observed out of order.
     pub fn store_atomic(&self, val: Head, ord: Ordering) {
         let atomic = unsafe { &*self.0 };
 
         atomic.store(val, ord);
     }
 
-    #[cfg(debug_assertions)]
-    pub fn store_atomic(&self, val: Head, ord: Ordering) {
-        let atomic = unsafe { &*self.0 };
-
-        let old = atomic.swap(val, ord);
-        debug_assert!(val >= old);
-    }
-
     // IDEA: does #[inline(always)] make a difference in benchmarks?
     pub fn load_atomic(&self, ord: Ordering) -> HeadCache {
         let atomic = unsafe { &*self.0 };
@@ -256,11 +348,12 @@ impl ReadWriteTail {
         atomic.load(ord)
     }
 
-    pub fn cas(&self, expected: i64, desired: i64, ord: Ordering) -> bool {
+    pub fn cas(&self, expected: Tail, desired: Tail, success: Ordering, failure: Ordering) -> bool {
         let atomic = unsafe { &*self.0 };
 
-        let original = atomic.compare_and_swap(expected, desired, ord);
-        original == expected
+        atomic
+            .compare_exchange(expected, desired, success, failure)
+            .is_ok()
     }
 }
 
@@ -277,7 +370,78 @@ impl ReadOnlyTail {
     }
 }
 
-#[cfg(test)]
+impl ReadWriteCorrelationCounter {
+    pub fn new(ptr: *const AtomicCorrelationCounter) -> Self {
+        Self(ptr)
+    }
+
+    pub fn fetch_add(&self, val: CorrelationCounter, ord: Ordering) -> CorrelationCounter {
+        let atomic = unsafe { &*self.0 };
+
+        atomic.fetch_add(val, ord)
+    }
+}
+
+impl ReadWriteConsumerHeartbeat {
+    pub fn new(ptr: *const AtomicConsumerHeartbeat) -> Self {
+        Self(ptr)
+    }
+
+    pub fn store_atomic(&self, val: ConsumerHeartbeat, ord: Ordering) {
+        let atomic = unsafe { &*self.0 };
+
+        atomic.store(val, ord);
+    }
+
+    pub fn load_atomic(&self, ord: Ordering) -> ConsumerHeartbeat {
+        let atomic = unsafe { &*self.0 };
+
+        atomic.load(ord)
+    }
+}
+
+impl ReadOnlyConsumerHeartbeat {
+    pub fn new(ptr: *const AtomicConsumerHeartbeat) -> Self {
+        Self(ptr)
+    }
+
+    pub fn load_atomic(&self, ord: Ordering) -> ConsumerHeartbeat {
+        let atomic = unsafe { &*self.0 };
+
+        atomic.load(ord)
+    }
+}
+
+impl ReadWriteConsumerParked {
+    pub fn new(ptr: *const AtomicConsumerParked) -> Self {
+        Self(ptr)
+    }
+
+    pub fn store_atomic(&self, val: ConsumerParked, ord: Ordering) {
+        let atomic = unsafe { &*self.0 };
+
+        atomic.store(val, ord);
+    }
+
+    pub fn load_atomic(&self, ord: Ordering) -> ConsumerParked {
+        let atomic = unsafe { &*self.0 };
+
+        atomic.load(ord)
+    }
+
+    pub fn swap_atomic(&self, val: ConsumerParked, ord: Ordering) -> ConsumerParked {
+        let atomic = unsafe { &*self.0 };
+
+        atomic.swap(val, ord)
+    }
+
+    /// The futex word itself.
+    pub fn as_atomic(&self) -> &AtomicConsumerParked {
+        unsafe { &*self.0 }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
 mod tests {
     use std::mem::{align_of, offset_of, size_of};
 
@@ -286,10 +450,12 @@ mod tests {
     use super::RawDescriptor;
 
     #[test]
+    #[allow(clippy::identity_op)]
     fn ringbuffer_descriptor_layout_alignment() {
         assert_eq!(size_of::<RawDescriptor>(), 6 * 2 * AERON_CACHE_LINE_LENGTH);
         assert_eq!(align_of::<RawDescriptor>(), 8);
 
+        assert_eq!(offset_of!(RawDescriptor, consumer_parked), 0);
         assert_eq!(
             offset_of!(RawDescriptor, tail_position),
             1 * 2 * AERON_CACHE_LINE_LENGTH
@@ -311,15 +477,4 @@ mod tests {
             5 * 2 * AERON_CACHE_LINE_LENGTH
         );
     }
-
-    // #[test]
-    // fn ringbuffer_data_layout_alignment() {
-    //     assert_eq!(
-    //         size_of::<RingBufferData<1024>>(),
-    //         size_of::<[UnsafeCell<u8>; 1024]>() + size_of::<UnsafeCell<RawDescriptor>>()
-    //     );
-
-    //     assert_eq!(offset_of!(RingBufferData<1024>, buffer), 0);
-    //     assert_eq!(offset_of!(RingBufferData<1024>, descriptor), 1024);
-    // }
 }
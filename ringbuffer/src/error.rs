@@ -0,0 +1,71 @@
+//! Errors returned by the ring buffer.
+
+use std::{fmt, io};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The capacity is not a power of two, or too small to hold a single record.
+    InvalidCapacity(usize),
+    /// The message is larger than the ring buffer accepts, it will never fit.
+    MessageTooLong {
+        length: usize,
+        max_message_length: usize,
+    },
+    /// The records of a batch together are longer than half the ring buffer, it may never find
+    /// room in one piece.
+    BatchTooLong {
+        length: usize,
+        max_batch_length: usize,
+    },
+    /// Message type ids must be positive, lower values are reserved for padding.
+    InvalidMsgTypeId(i32),
+    /// The ring buffer is currently too full, retrying once the receiver has caught up may succeed.
+    InsufficientCapacity,
+    /// The memory does not meet the alignment the ring buffer requires.
+    MisalignedBuffer { alignment: usize },
+}
+
+impl Error {
+    /// Returns `true` if the operation failed because of back-pressure and may be retried, any
+    /// other error fails again however long the receiver takes to catch up.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::InsufficientCapacity)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCapacity(capacity) => write!(f, "Invalid capacity: {capacity}"),
+            Self::MessageTooLong {
+                length,
+                max_message_length,
+            } => write!(
+                f,
+                "Message length {length} exceeds the max message length of {max_message_length}"
+            ),
+            Self::BatchTooLong {
+                length,
+                max_batch_length,
+            } => write!(
+                f,
+                "Batch length {length} exceeds the max batch length of {max_batch_length}"
+            ),
+            Self::InvalidMsgTypeId(msg_type_id) => {
+                write!(f, "Invalid message type id: {msg_type_id}")
+            }
+            Self::InsufficientCapacity => write!(f, "Insufficient capacity in the ring buffer"),
+            Self::MisalignedBuffer { alignment } => {
+                write!(f, "Buffer is not aligned to {alignment} bytes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
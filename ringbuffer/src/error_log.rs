@@ -0,0 +1,463 @@
+//! Log of distinct errors in shared memory, following Aeron's `aeron_distinct_error_log`.
+//!
+//! Every distinct error is written once, as a record holding its observation count, the times it
+//! was first and last observed, and its encoding:
+//!
+//! ```text
+//! 0        4                   8                            16                            24
+//! +--------+-------------------+----------------------------+-----------------------------+-------------
+//! | length | observation count | last observation timestamp | first observation timestamp | encoded ...
+//! +--------+-------------------+----------------------------+-----------------------------+-------------
+//! ```
+//!
+//! Records are aligned to [`RECORD_ALIGNMENT`] and published by an ordered store of their length,
+//! like ring buffer records. Observing the same error again only bumps its count and last
+//! observation timestamp. An [`ErrorLogReader`], typically in another process, reads the records.
+
+use std::{
+    error::Error as StdError,
+    fmt::Write,
+    io,
+    mem::{align_of, size_of},
+    path::Path,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicI32, AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    aeron_align,
+    backing::Backing,
+    clock::{EpochClock, SystemEpochClock},
+    error::Error,
+};
+
+/// Offset of the encoded error in a record.
+pub const ENCODED_ERROR_OFFSET: usize = size_of::<ErrorRecordHeader>();
+pub const RECORD_ALIGNMENT: usize = size_of::<i64>();
+
+#[repr(C)]
+struct ErrorRecordHeader {
+    length: AtomicI32,
+    observation_count: AtomicI32,
+    last_observation_timestamp: AtomicI64,
+    first_observation_timestamp: AtomicI64,
+}
+
+const _: () = assert!(ENCODED_ERROR_OFFSET == 24);
+
+/// Memory of an error log, shared by the writer and the readers.
+#[derive(Debug, Clone)]
+struct ErrorLogBuffer {
+    buffer: NonNull<u8>,
+    capacity: usize,
+    backing: Arc<Backing>,
+}
+
+unsafe impl Send for ErrorLogBuffer {}
+unsafe impl Sync for ErrorLogBuffer {}
+
+impl ErrorLogBuffer {
+    fn from_backing(backing: Backing) -> Result<Self, Error> {
+        let buffer = backing.as_ptr();
+
+        if buffer.align_offset(align_of::<ErrorRecordHeader>()) != 0 {
+            return Err(Error::MisalignedBuffer {
+                alignment: align_of::<ErrorRecordHeader>(),
+            });
+        }
+
+        Ok(Self {
+            buffer: NonNull::new(buffer).unwrap(),
+            capacity: backing.len(),
+            backing: Arc::new(backing),
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `offset` must be aligned to [`RECORD_ALIGNMENT`] and leave room for a header before the
+    /// capacity.
+    unsafe fn header(&self, offset: usize) -> &ErrorRecordHeader {
+        unsafe { &*(self.buffer.as_ptr().byte_add(offset) as *const ErrorRecordHeader) }
+    }
+}
+
+/// Writes distinct errors to the log.
+///
+/// There must be only one writer per log, it can be shared between threads.
+#[derive(Debug)]
+pub struct DistinctErrorLog<C: EpochClock = SystemEpochClock> {
+    log_buffer: ErrorLogBuffer,
+    clock: C,
+    observations: Mutex<Observations>,
+}
+
+#[derive(Debug, Default)]
+struct Observations {
+    next_offset: usize,
+    /// Encoded error and offset of the record of every distinct error written.
+    distinct: Vec<(String, usize)>,
+}
+
+impl<C: EpochClock> DistinctErrorLog<C> {
+    /// Writes errors to `backing`, which must be zeroed.
+    pub fn new(backing: Backing, clock: C) -> Result<Self, Error> {
+        Ok(Self {
+            log_buffer: ErrorLogBuffer::from_backing(backing)?,
+            clock,
+            observations: Mutex::new(Observations::default()),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.log_buffer.capacity
+    }
+
+    /// Records an observation of `error`, returns `false` if it is new and the log is too full to
+    /// hold it.
+    ///
+    /// Errors are distinct by their `Debug` and `Display` output and those of their sources, which
+    /// stand in for the stack trace Aeron compares. `Debug` goes through the error itself, so errors
+    /// of different types stay distinct even when recorded as `&dyn Error`.
+    pub fn record<E>(&self, error: &E) -> bool
+    where
+        E: StdError + ?Sized,
+    {
+        let timestamp = self.clock.time();
+        let encoded = encode(error);
+
+        let mut observations = self.observations.lock().unwrap();
+        let offset = match observations
+            .distinct
+            .iter()
+            .find(|(distinct, _)| *distinct == encoded)
+        {
+            Some(&(_, offset)) => offset,
+            None => match self.new_observation(&mut observations, timestamp, encoded) {
+                Some(offset) => offset,
+                None => return false,
+            },
+        };
+        drop(observations);
+
+        let header = unsafe { self.log_buffer.header(offset) };
+        header.observation_count.fetch_add(1, Ordering::AcqRel);
+        header
+            .last_observation_timestamp
+            .store(timestamp, Ordering::Release);
+
+        true
+    }
+
+    fn new_observation(
+        &self,
+        observations: &mut Observations,
+        timestamp: i64,
+        encoded: String,
+    ) -> Option<usize> {
+        let length = ENCODED_ERROR_OFFSET + encoded.len();
+        let offset = observations.next_offset;
+
+        if offset + length > self.log_buffer.capacity || length > i32::MAX as usize {
+            return None;
+        }
+
+        let header = unsafe {
+            self.log_buffer
+                .buffer
+                .as_ptr()
+                .byte_add(offset + ENCODED_ERROR_OFFSET)
+                .copy_from_nonoverlapping(encoded.as_ptr(), encoded.len());
+            self.log_buffer.header(offset)
+        };
+        header
+            .first_observation_timestamp
+            .store(timestamp, Ordering::Relaxed);
+        // Readers acquire the length before reading the rest of the record.
+        header.length.store(length as i32, Ordering::Release);
+
+        observations.next_offset = aeron_align(offset + length, RECORD_ALIGNMENT);
+        observations.distinct.push((encoded, offset));
+
+        Some(offset)
+    }
+}
+
+/// `Debug` and `Display` output of `error` and of its sources, one per line.
+fn encode<E>(error: &E) -> String
+where
+    E: StdError + ?Sized,
+{
+    let mut encoded = format!("{error:?}: {error}");
+
+    let mut source = error.source();
+    while let Some(cause) = source {
+        write!(encoded, "\nCaused by: {cause:?}: {cause}").unwrap();
+        source = cause.source();
+    }
+
+    encoded
+}
+
+/// Reads the records of an error log written by a [`DistinctErrorLog`].
+#[derive(Debug, Clone)]
+pub struct ErrorLogReader {
+    log_buffer: ErrorLogBuffer,
+}
+
+impl ErrorLogReader {
+    pub fn new(backing: Backing) -> Result<Self, Error> {
+        Ok(Self {
+            log_buffer: ErrorLogBuffer::from_backing(backing)?,
+        })
+    }
+
+    /// Reads the error log in a file created with [`Backing::create_file`].
+    pub fn open_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(Backing::open_file(path)?)?)
+    }
+
+    /// Returns `true` if at least one error was recorded.
+    pub fn has_errors(&self) -> bool {
+        self.log_buffer.capacity >= ENCODED_ERROR_OFFSET
+            && unsafe { self.log_buffer.header(0) }
+                .length
+                .load(Ordering::Acquire)
+                != 0
+    }
+
+    /// Calls `handler` with the observation count, first and last observation timestamps and
+    /// encoding of every error last observed at or after `since_timestamp`, returns the number of
+    /// errors handled.
+    pub fn read<F>(&self, mut handler: F, since_timestamp: i64) -> usize
+    where
+        F: FnMut(i32, i64, i64, &str),
+    {
+        let mut entries = 0;
+        let mut offset = 0;
+
+        while offset + ENCODED_ERROR_OFFSET <= self.log_buffer.capacity {
+            let header = unsafe { self.log_buffer.header(offset) };
+            let length = header.length.load(Ordering::Acquire) as usize;
+            if length < ENCODED_ERROR_OFFSET || offset + length > self.log_buffer.capacity {
+                break;
+            }
+
+            let last_observation_timestamp =
+                header.last_observation_timestamp.load(Ordering::Acquire);
+            if last_observation_timestamp >= since_timestamp {
+                entries += 1;
+
+                let encoded = unsafe {
+                    std::slice::from_raw_parts(
+                        self.log_buffer
+                            .buffer
+                            .as_ptr()
+                            .byte_add(offset + ENCODED_ERROR_OFFSET),
+                        length - ENCODED_ERROR_OFFSET,
+                    )
+                };
+                handler(
+                    header.observation_count.load(Ordering::Acquire),
+                    header.first_observation_timestamp.load(Ordering::Relaxed),
+                    last_observation_timestamp,
+                    &String::from_utf8_lossy(encoded),
+                );
+            }
+
+            offset += aeron_align(length, RECORD_ALIGNMENT);
+        }
+
+        entries
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::{error::Error as StdError, fmt, io};
+
+    use super::{DistinctErrorLog, ErrorLogReader, ENCODED_ERROR_OFFSET};
+    use crate::{backing::Backing, clock::CachedEpochClock};
+
+    #[derive(Debug)]
+    struct TestError(&'static str, Option<io::Error>);
+
+    impl fmt::Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl StdError for TestError {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            self.1.as_ref().map(|error| error as _)
+        }
+    }
+
+    fn read_all(reader: &ErrorLogReader, since_timestamp: i64) -> Vec<(i32, i64, i64, String)> {
+        let mut errors = Vec::new();
+        reader.read(
+            |count, first, last, encoded| errors.push((count, first, last, encoded.to_owned())),
+            since_timestamp,
+        );
+        errors
+    }
+
+    fn error_log(
+        length: usize,
+        clock: &CachedEpochClock,
+    ) -> (DistinctErrorLog<&CachedEpochClock>, ErrorLogReader) {
+        let error_log = DistinctErrorLog::new(Backing::heap(length), clock).unwrap();
+        let reader = ErrorLogReader {
+            log_buffer: error_log.log_buffer.clone(),
+        };
+        (error_log, reader)
+    }
+
+    #[test]
+    fn repeated_error_only_bumps_its_record() {
+        let clock = CachedEpochClock::new(100);
+        let (error_log, reader) = error_log(1024, &clock);
+        assert!(!reader.has_errors());
+
+        assert!(error_log.record(&TestError("first", None)));
+        clock.advance(10);
+        assert!(error_log.record(&TestError("second", None)));
+        clock.advance(10);
+        assert!(error_log.record(&TestError("first", None)));
+
+        let errors = read_all(&reader, 0);
+        assert!(reader.has_errors());
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].0, errors[0].1, errors[0].2), (2, 100, 120));
+        assert_eq!((errors[1].0, errors[1].1, errors[1].2), (1, 110, 110));
+        assert_eq!(errors[0].3, r#"TestError("first", None): first"#);
+        assert_eq!(errors[1].3, r#"TestError("second", None): second"#);
+    }
+
+    #[test]
+    fn sources_make_errors_distinct() {
+        let clock = CachedEpochClock::new(0);
+        let (error_log, reader) = error_log(1024, &clock);
+
+        let error = TestError("wrapped", Some(io::Error::other("disk")));
+        assert!(error_log.record(&error));
+        assert!(error_log.record(&TestError("wrapped", None)));
+        assert!(error_log.record(&io::Error::other("disk")));
+
+        let errors = read_all(&reader, 0);
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0].3,
+            format!(
+                "{error:?}: wrapped\nCaused by: {:?}: disk",
+                error.1.as_ref().unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn same_message_of_another_type_is_distinct_through_dyn_error() {
+        #[derive(Debug)]
+        struct OtherError;
+
+        impl fmt::Display for OtherError {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("same")
+            }
+        }
+
+        impl StdError for OtherError {}
+
+        let clock = CachedEpochClock::new(0);
+        let (error_log, reader) = error_log(1024, &clock);
+
+        let errors: [&dyn StdError; 3] = [&TestError("same", None), &OtherError, &OtherError];
+        for error in errors {
+            assert!(error_log.record(error));
+        }
+
+        let errors = read_all(&reader, 0);
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].0, errors[1].0), (1, 2));
+        assert_eq!(errors[0].3, r#"TestError("same", None): same"#);
+        assert_eq!(errors[1].3, "OtherError: same");
+    }
+
+    #[test]
+    fn read_skips_errors_last_observed_before_since_timestamp() {
+        let clock = CachedEpochClock::new(0);
+        let (error_log, reader) = error_log(1024, &clock);
+
+        error_log.record(&TestError("old", None));
+        clock.advance(50);
+        error_log.record(&TestError("new", None));
+
+        let errors = read_all(&reader, 50);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].3.ends_with(": new"));
+    }
+
+    #[test]
+    fn full_log_rejects_new_errors_but_counts_known_ones() {
+        let clock = CachedEpochClock::new(0);
+        let error = TestError("fits", None);
+        let (error_log, reader) =
+            error_log(ENCODED_ERROR_OFFSET + super::encode(&error).len(), &clock);
+
+        assert!(error_log.record(&error));
+        assert!(!error_log.record(&TestError("does not fit", None)));
+        assert!(error_log.record(&error));
+
+        assert_eq!(read_all(&reader, 0)[0].0, 2);
+    }
+
+    #[test]
+    fn errors_from_many_threads_are_counted() {
+        let clock = CachedEpochClock::new(0);
+        let (error_log, reader) = error_log(4096, &clock);
+
+        std::thread::scope(|s| {
+            for thread in 0..4 {
+                let error_log = &error_log;
+                s.spawn(move || {
+                    for i in 0..1_000 {
+                        let message = if i % 2 == 0 { "even" } else { "odd" };
+                        error_log.record(&TestError(message, None));
+                        if thread == 0 && i % 100 == 0 {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+        });
+
+        let counts: Vec<i32> = read_all(&reader, 0)
+            .into_iter()
+            .map(|(count, ..)| count)
+            .collect();
+        assert_eq!(counts, [2_000, 2_000]);
+    }
+
+    #[test]
+    fn reader_sees_errors_through_another_mapping() {
+        let path = std::env::temp_dir().join(format!("agrona-error-log-{}", std::process::id()));
+        let clock = CachedEpochClock::new(7);
+        let error_log =
+            DistinctErrorLog::new(Backing::create_file(&path, 1024).unwrap(), &clock).unwrap();
+        let reader = ErrorLogReader::open_file(&path).unwrap();
+
+        error_log.record(&io::Error::other("mapped"));
+
+        let errors = read_all(&reader, 0);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].0, errors[0].1, errors[0].2), (1, 7, 7));
+        assert!(errors[0].3.ends_with(": mapped"));
+
+        drop((error_log, reader));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,464 @@
+//! Ring buffer with a capacity fixed at compile time.
+//!
+//! [`RingBuffer<N>`] holds its buffer and [`RawDescriptor`] trailer inline, laid out exactly like
+//! the memory of a runtime [`crate::RingBuffer`], so it can live in a `static` or on the stack
+//! instead of the heap. An invalid capacity fails to compile, and since the capacity is a constant
+//! the shared record handling in [`crate::sender`] and [`crate::receiver`] is compiled with a
+//! constant mask.
+//!
+//! Under loom the record headers are kept aside, like in [`Backing::heap`](crate::backing::Backing),
+//! so the ring buffer can't be built in a const context and holds a pointer to them in the cache
+//! line in front of its buffer.
+
+use std::{
+    cell::UnsafeCell,
+    io::IoSlice,
+    marker::PhantomData,
+    mem::offset_of,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[cfg(loom)]
+use crate::{RecordDescriptor, AERON_CACHE_LINE_LENGTH, AERON_RB_ALIGNMENT};
+
+use crate::{
+    aeron_rb_max_message_length, aeron_rb_size,
+    descriptor::{Descriptor, RawDescriptor, ReceiverDescriptor, SenderDescriptor},
+    error::Error,
+    receiver::{self, Batch, ControlledReadAction},
+    sender::{self, Claim},
+    wakers::Wakers,
+    AERON_MPSC_RB_MIN_CAPACITY,
+};
+
+/// Multi producer, single consumer ring buffer of `N` bytes.
+///
+/// Hands out any number of [`Sender`]s and one [`Receiver`] at a time.
+///
+/// ```
+/// use agrona::fixed::RingBuffer;
+///
+/// static RING_BUFFER: RingBuffer<1024> = RingBuffer::new();
+///
+/// let mut sender = RING_BUFFER.sender();
+/// let mut receiver = RING_BUFFER.receiver().unwrap();
+///
+/// sender.send(1, b"hello").unwrap();
+/// assert_eq!(receiver.receive(1), [(1, b"hello".to_vec())]);
+/// ```
+///
+/// A capacity that isn't a power of two doesn't compile:
+///
+/// ```compile_fail
+/// let ring_buffer = agrona::fixed::RingBuffer::<1000>::new();
+/// ```
+#[repr(C, align(64))]
+pub struct RingBuffer<const N: usize> {
+    #[cfg(loom)]
+    loom_headers: LoomHeaders,
+    buffer: [UnsafeCell<u8>; N],
+    descriptor: RawDescriptor,
+    receiver_taken: AtomicBool,
+    wakers: Wakers,
+}
+
+// The memory is only accessed through the ring buffer protocol.
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+/// Record headers of a ring buffer under loom, the pointer to them right in front of the buffer
+/// where [`record_header`](crate::record_header) looks for it.
+#[cfg(loom)]
+#[repr(C, align(64))]
+struct LoomHeaders {
+    headers: Box<[RecordDescriptor]>,
+    _pad: [u8; AERON_CACHE_LINE_LENGTH
+        - size_of::<Box<[RecordDescriptor]>>()
+        - size_of::<*const RecordDescriptor>()],
+    ptr: *const RecordDescriptor,
+}
+
+#[cfg(loom)]
+unsafe impl Send for LoomHeaders {}
+#[cfg(loom)]
+unsafe impl Sync for LoomHeaders {}
+
+impl<const N: usize> RingBuffer<N> {
+    const CAPACITY: usize = {
+        assert!(
+            N.is_power_of_two() && N >= AERON_MPSC_RB_MIN_CAPACITY,
+            "ring buffer capacity must be a power of two of at least 8 bytes"
+        );
+        N
+    };
+
+    pub const MAX_MESSAGE_LENGTH: usize =
+        aeron_rb_max_message_length(Self::CAPACITY, AERON_MPSC_RB_MIN_CAPACITY);
+
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        // Fails the build for an invalid capacity.
+        let _ = Self::CAPACITY;
+
+        Self {
+            buffer: [const { UnsafeCell::new(0) }; N],
+            descriptor: RawDescriptor::new(),
+            receiver_taken: AtomicBool::new(false),
+            wakers: Wakers::new(),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        let _ = Self::CAPACITY;
+
+        let headers: Box<[RecordDescriptor]> = (0..N / AERON_RB_ALIGNMENT)
+            .map(|_| RecordDescriptor::new())
+            .collect();
+
+        Self {
+            loom_headers: LoomHeaders {
+                ptr: headers.as_ptr(),
+                headers,
+                _pad: [0; AERON_CACHE_LINE_LENGTH
+                    - size_of::<Box<[RecordDescriptor]>>()
+                    - size_of::<*const RecordDescriptor>()],
+            },
+            buffer: [const { UnsafeCell::new(0) }; N],
+            descriptor: RawDescriptor::new(),
+            receiver_taken: AtomicBool::new(false),
+            wakers: Wakers::new(),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        Self::CAPACITY
+    }
+
+    pub const fn max_message_length(&self) -> usize {
+        Self::MAX_MESSAGE_LENGTH
+    }
+
+    /// Position up to which producers have claimed space, see [`crate::RingBuffer::size`].
+    pub fn producer_position(&self) -> i64 {
+        self.descriptor.tail_position.load(Ordering::Acquire)
+    }
+
+    /// Position up to which the receiver has consumed.
+    pub fn consumer_position(&self) -> i64 {
+        self.descriptor.head_position.load(Ordering::Acquire)
+    }
+
+    /// Number of bytes currently claimed in the ring buffer, see [`crate::RingBuffer::size`].
+    pub fn size(&self) -> usize {
+        aeron_rb_size(
+            Self::CAPACITY,
+            || self.descriptor.head_position.load(Ordering::Acquire),
+            || self.descriptor.tail_position.load(Ordering::Acquire),
+        )
+    }
+
+    pub fn sender(&self) -> Sender<'_, N> {
+        Sender {
+            buffer: self.buffer(),
+            descriptor: SenderDescriptor::new(self.descriptor(), &self.wakers),
+            ring_buffer: PhantomData,
+        }
+    }
+
+    /// Returns the receiver, or `None` while another one is alive.
+    pub fn receiver(&self) -> Option<Receiver<'_, N>> {
+        if self.receiver_taken.swap(true, Ordering::Acquire) {
+            return None;
+        }
+
+        Some(Receiver {
+            buffer: self.buffer(),
+            descriptor: ReceiverDescriptor::new(self.descriptor(), &self.wakers),
+            receiver_taken: &self.receiver_taken,
+        })
+    }
+
+    pub fn split(&mut self) -> (Sender<'_, N>, Receiver<'_, N>) {
+        // Any receiver handed out before has been dropped or leaked, either way it is gone.
+        *self.receiver_taken.get_mut() = false;
+
+        (self.sender(), self.receiver().unwrap())
+    }
+
+    /// Derived from the whole ring buffer, under loom the record handling reads in front of it.
+    fn buffer(&self) -> NonNull<u8> {
+        unsafe {
+            NonNull::from(self)
+                .cast::<u8>()
+                .byte_add(offset_of!(Self, buffer))
+        }
+    }
+
+    fn descriptor(&self) -> Descriptor {
+        Descriptor::new(&self.descriptor as *const RawDescriptor as *mut u8)
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Producer on a [`RingBuffer<N>`], every clone is an additional producer.
+#[derive(Clone)]
+pub struct Sender<'a, const N: usize> {
+    buffer: NonNull<u8>,
+    descriptor: SenderDescriptor,
+    ring_buffer: PhantomData<&'a RingBuffer<N>>,
+}
+
+unsafe impl<const N: usize> Send for Sender<'_, N> {}
+
+impl<const N: usize> Sender<'_, N> {
+    pub const fn capacity(&self) -> usize {
+        RingBuffer::<N>::CAPACITY
+    }
+
+    pub const fn max_message_length(&self) -> usize {
+        RingBuffer::<N>::MAX_MESSAGE_LENGTH
+    }
+
+    pub fn send(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), Error> {
+        self.send_vectored(msg_type_id, &[IoSlice::new(msg)])
+    }
+
+    /// See [`crate::sender::Sender::send_vectored`].
+    pub fn send_vectored(&mut self, msg_type_id: i32, parts: &[IoSlice<'_>]) -> Result<(), Error> {
+        unsafe {
+            sender::send_vectored(
+                self.buffer,
+                RingBuffer::<N>::CAPACITY,
+                RingBuffer::<N>::MAX_MESSAGE_LENGTH,
+                &self.descriptor,
+                msg_type_id,
+                parts,
+            )
+        }
+    }
+
+    /// See [`crate::sender::Sender::send_batch`].
+    pub fn send_batch(&mut self, messages: &[(i32, &[u8])]) -> Result<usize, Error> {
+        unsafe {
+            sender::send_batch(
+                self.buffer,
+                RingBuffer::<N>::CAPACITY,
+                RingBuffer::<N>::MAX_MESSAGE_LENGTH,
+                &self.descriptor,
+                messages,
+            )
+        }
+    }
+
+    /// See [`crate::sender::Sender::send_async`].
+    #[cfg(all(feature = "async", not(loom)))]
+    pub async fn send_async(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), Error> {
+        std::future::poll_fn(|cx| unsafe {
+            sender::poll_send(
+                cx,
+                self.buffer,
+                RingBuffer::<N>::CAPACITY,
+                RingBuffer::<N>::MAX_MESSAGE_LENGTH,
+                &self.descriptor,
+                msg_type_id,
+                msg,
+            )
+        })
+        .await
+    }
+
+    /// See [`crate::sender::Sender::try_claim`].
+    pub fn try_claim(&mut self, msg_type_id: i32, length: usize) -> Result<Claim<'_>, Error> {
+        unsafe {
+            sender::try_claim(
+                self.buffer,
+                RingBuffer::<N>::CAPACITY,
+                RingBuffer::<N>::MAX_MESSAGE_LENGTH,
+                &self.descriptor,
+                msg_type_id,
+                length,
+            )
+        }
+    }
+}
+
+/// The consumer of a [`RingBuffer<N>`].
+pub struct Receiver<'a, const N: usize> {
+    buffer: NonNull<u8>,
+    descriptor: ReceiverDescriptor,
+    receiver_taken: &'a AtomicBool,
+}
+
+unsafe impl<const N: usize> Send for Receiver<'_, N> {}
+
+impl<const N: usize> Receiver<'_, N> {
+    pub const fn capacity(&self) -> usize {
+        RingBuffer::<N>::CAPACITY
+    }
+
+    pub const fn max_message_length(&self) -> usize {
+        RingBuffer::<N>::MAX_MESSAGE_LENGTH
+    }
+
+    /// See [`crate::receiver::Receiver::receive`].
+    pub fn receive(&mut self, message_count_limit: usize) -> Vec<(i32, Vec<u8>)> {
+        let mut read_buffer: Vec<(i32, Vec<u8>)> = Vec::new();
+
+        self.read(
+            |msg_type_id, data| read_buffer.push((msg_type_id, data.to_vec())),
+            message_count_limit,
+        );
+
+        read_buffer
+    }
+
+    /// See [`crate::receiver::Receiver::read`].
+    pub fn read<F>(&mut self, mut handler: F, message_count_limit: usize) -> usize
+    where
+        F: FnMut(i32, &[u8]),
+    {
+        self.controlled_read(
+            |msg_type_id, data| {
+                handler(msg_type_id, data);
+                ControlledReadAction::Continue
+            },
+            message_count_limit,
+        )
+    }
+
+    /// See [`crate::receiver::Receiver::controlled_read`].
+    pub fn controlled_read<F>(&mut self, handler: F, message_count_limit: usize) -> usize
+    where
+        F: FnMut(i32, &[u8]) -> ControlledReadAction,
+    {
+        unsafe {
+            receiver::controlled_read(
+                self.buffer,
+                RingBuffer::<N>::CAPACITY,
+                &self.descriptor,
+                handler,
+                message_count_limit,
+            )
+        }
+    }
+
+    /// See [`crate::receiver::Receiver::poll`].
+    pub fn poll(&mut self, message_count_limit: usize) -> Batch<'_> {
+        unsafe {
+            receiver::poll(
+                self.buffer,
+                RingBuffer::<N>::CAPACITY,
+                &self.descriptor,
+                message_count_limit,
+            )
+        }
+    }
+
+    /// See [`crate::receiver::Receiver::unblock`].
+    pub fn unblock(&mut self) -> bool {
+        unsafe { receiver::unblock(self.buffer, RingBuffer::<N>::CAPACITY, &self.descriptor) }
+    }
+}
+
+impl<const N: usize> Drop for Receiver<'_, N> {
+    fn drop(&mut self) {
+        self.receiver_taken.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use super::RingBuffer;
+    use crate::{error::Error, AERON_RB_TRAILER_LENGTH};
+
+    #[test]
+    fn ring_buffer_layout_matches_runtime_memory() {
+        assert_eq!(offset_of!(RingBuffer<1024>, buffer), 0);
+        assert_eq!(offset_of!(RingBuffer<1024>, descriptor), 1024);
+        assert!(size_of::<RingBuffer<1024>>() >= 1024 + AERON_RB_TRAILER_LENGTH);
+        assert_eq!(RingBuffer::<1024>::MAX_MESSAGE_LENGTH, 128);
+    }
+
+    #[test]
+    fn only_one_receiver_at_a_time() {
+        let ring_buffer = RingBuffer::<64>::new();
+
+        let receiver = ring_buffer.receiver().unwrap();
+        assert!(ring_buffer.receiver().is_none());
+        drop(receiver);
+        assert!(ring_buffer.receiver().is_some());
+    }
+
+    #[test]
+    fn stack_ring_buffer_wraps() {
+        let mut ring_buffer = RingBuffer::<64>::new();
+        let (mut sender, mut receiver) = ring_buffer.split();
+
+        for round in 0..20u8 {
+            sender.send(1, &[round; 5]).unwrap();
+            let mut claim = sender.try_claim(2, 3).unwrap();
+            claim.copy_from_slice(&[round; 3]);
+            claim.commit();
+
+            assert_eq!(
+                receiver.receive(10),
+                [(1, vec![round; 5]), (2, vec![round; 3])]
+            );
+        }
+
+        assert_eq!(
+            sender.send(1, &[0; 9]),
+            Err(Error::MessageTooLong {
+                length: 9,
+                max_message_length: 8
+            })
+        );
+    }
+
+    #[test]
+    fn static_ring_buffer_multi_producer() {
+        static RING_BUFFER: RingBuffer<1024> = RingBuffer::new();
+        let messages_per_producer: u32 = 10_000;
+
+        let mut receiver = RING_BUFFER.receiver().unwrap();
+
+        std::thread::scope(|s| {
+            for producer in 1..=2 {
+                let mut sender = RING_BUFFER.sender();
+                s.spawn(move || {
+                    for sequence in 0..messages_per_producer {
+                        while sender.send(producer, &sequence.to_le_bytes()).is_err() {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            let mut next_sequence = [0u32; 2];
+            while next_sequence.iter().any(|&n| n < messages_per_producer) {
+                let count = receiver.read(
+                    |msg_type_id, data| {
+                        let producer = msg_type_id as usize - 1;
+                        assert_eq!(data, next_sequence[producer].to_le_bytes());
+                        next_sequence[producer] += 1;
+                    },
+                    usize::MAX,
+                );
+
+                if count == 0 {
+                    std::thread::yield_now();
+                }
+            }
+        });
+
+        assert_eq!(RING_BUFFER.size(), 0);
+    }
+}
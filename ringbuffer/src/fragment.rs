@@ -0,0 +1,437 @@
+//! Fragmentation of messages larger than the max message length of a ring buffer.
+//!
+//! A [`FragmentingSender`] splits a message into records that each start with a fragment header:
+//!
+//! ```text
+//! 0             8       9          12
+//! +-------------+-------+----------+-------------
+//! | producer id | flags | reserved | payload ...
+//! +-------------+-------+----------+-------------
+//! ```
+//!
+//! The producer id, little endian, comes from the ring buffer correlation counter so it is unique
+//! among all producers, and the flags mark the [`BEGIN_FLAG`] and [`END_FLAG`] fragments of a
+//! message. A [`FragmentAssembler`] on the receiving side joins the fragments of every producer
+//! back into whole messages.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{
+    aeron_align, error::Error, sender::Sender, AERON_RB_ALIGNMENT, AERON_RB_RECORD_HEADER_LENGTH,
+};
+
+pub const FRAGMENT_HEADER_LENGTH: usize = 12;
+/// Set on the first fragment of a message.
+pub const BEGIN_FLAG: u8 = 0x80;
+/// Set on the last fragment of a message.
+pub const END_FLAG: u8 = 0x40;
+
+/// Sender that splits messages into fragments, see the [module documentation](self).
+///
+/// All producers on a ring buffer read with a [`FragmentAssembler`] have to be fragmenting
+/// senders, a message of a single fragment has both flags set.
+pub struct FragmentingSender {
+    sender: Sender,
+    producer_id: i64,
+    max_payload_length: usize,
+}
+
+impl FragmentingSender {
+    pub fn new(sender: Sender) -> Result<Self, Error> {
+        if sender.max_message_length() <= FRAGMENT_HEADER_LENGTH {
+            return Err(Error::InvalidCapacity(sender.capacity()));
+        }
+
+        Ok(Self {
+            producer_id: sender.next_correlation_id(),
+            max_payload_length: sender.max_message_length() - FRAGMENT_HEADER_LENGTH,
+            sender,
+        })
+    }
+
+    pub fn producer_id(&self) -> i64 {
+        self.producer_id
+    }
+
+    /// Largest message that fits in the ring buffer once fragmented, leaving room for the padding
+    /// where the fragments wrap around.
+    pub fn max_message_length(&self) -> usize {
+        let record_length = Self::record_length(self.max_payload_length);
+
+        (self.sender.capacity() - record_length) / record_length * self.max_payload_length
+    }
+
+    /// Sends `msg` in as many fragments as needed.
+    ///
+    /// Fails without sending anything if the ring buffer can't currently hold all fragments. When
+    /// another producer takes the space first, the fragments already sent are discarded by the
+    /// assembler and the message can be sent again.
+    pub fn send(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), Error> {
+        let max_message_length = self.max_message_length();
+        if msg.len() > max_message_length {
+            return Err(Error::MessageTooLong {
+                length: msg.len(),
+                max_message_length,
+            });
+        }
+
+        let required_capacity: usize = self
+            .fragments(msg)
+            .map(|(_, payload)| Self::record_length(payload.len()))
+            .sum();
+
+        if required_capacity > self.sender.capacity() - self.sender.size() {
+            return Err(Error::InsufficientCapacity);
+        }
+
+        for (flags, payload) in self.fragments(msg) {
+            let mut claim = self
+                .sender
+                .try_claim(msg_type_id, FRAGMENT_HEADER_LENGTH + payload.len())?;
+
+            claim[..8].copy_from_slice(&self.producer_id.to_le_bytes());
+            claim[8] = flags;
+            claim[9..FRAGMENT_HEADER_LENGTH].fill(0);
+            claim[FRAGMENT_HEADER_LENGTH..].copy_from_slice(payload);
+            claim.commit();
+        }
+
+        Ok(())
+    }
+
+    fn fragments<'a>(&self, msg: &'a [u8]) -> impl Iterator<Item = (u8, &'a [u8])> {
+        let count = msg.len().div_ceil(self.max_payload_length).max(1);
+        let chunks = msg
+            .chunks(self.max_payload_length)
+            .chain(msg.is_empty().then_some(msg));
+
+        chunks.enumerate().map(move |(index, payload)| {
+            let mut flags = 0;
+            if index == 0 {
+                flags |= BEGIN_FLAG;
+            }
+            if index == count - 1 {
+                flags |= END_FLAG;
+            }
+
+            (flags, payload)
+        })
+    }
+
+    fn record_length(payload_length: usize) -> usize {
+        aeron_align(
+            AERON_RB_RECORD_HEADER_LENGTH + FRAGMENT_HEADER_LENGTH + payload_length,
+            AERON_RB_ALIGNMENT,
+        )
+    }
+}
+
+/// Joins fragments back into messages, per producer, see
+/// [`Receiver::read_fragmented`](crate::receiver::Receiver::read_fragmented).
+///
+/// Each producer's partial message is held in a buffer of at most `max_message_length` bytes,
+/// messages that turn out longer are dropped. A partial message is also dropped when the next
+/// message of its producer begins before it ended.
+///
+/// Partial messages are kept for at most `max_partials` producers at a time, and released as soon
+/// as they complete or are dropped. When a message begins while all of them are taken, the one
+/// that began longest ago is dropped to make room, which is where a producer that died halfway
+/// through a message ends up.
+#[derive(Debug)]
+pub struct FragmentAssembler {
+    max_message_length: usize,
+    max_partials: usize,
+    partials: HashMap<i64, Partial>,
+    begin_count: u64,
+}
+
+#[derive(Debug)]
+struct Partial {
+    msg_type_id: i32,
+    buffer: Vec<u8>,
+    /// Value of the begin count when the message began, the lowest began longest ago.
+    began: u64,
+}
+
+impl FragmentAssembler {
+    pub const DEFAULT_MAX_PARTIALS: usize = 64;
+
+    pub fn new(max_message_length: usize) -> Self {
+        Self::with_max_partials(max_message_length, Self::DEFAULT_MAX_PARTIALS)
+    }
+
+    pub fn with_max_partials(max_message_length: usize, max_partials: usize) -> Self {
+        assert!(max_partials > 0);
+
+        Self {
+            max_message_length,
+            max_partials,
+            partials: HashMap::new(),
+            begin_count: 0,
+        }
+    }
+
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
+
+    pub fn max_partials(&self) -> usize {
+        self.max_partials
+    }
+
+    /// Number of producers with a partial message.
+    pub fn partial_count(&self) -> usize {
+        self.partials.len()
+    }
+
+    /// Handles one fragment, calling `handler` once it completes a message.
+    pub fn on_fragment<F>(&mut self, msg_type_id: i32, fragment: &[u8], handler: &mut F)
+    where
+        F: FnMut(i32, &[u8]),
+    {
+        if fragment.len() < FRAGMENT_HEADER_LENGTH {
+            return;
+        }
+
+        let producer_id = i64::from_le_bytes(fragment[..8].try_into().unwrap());
+        let flags = fragment[8];
+        let payload = &fragment[FRAGMENT_HEADER_LENGTH..];
+
+        if flags & (BEGIN_FLAG | END_FLAG) == BEGIN_FLAG | END_FLAG {
+            self.partials.remove(&producer_id);
+            if payload.len() <= self.max_message_length {
+                handler(msg_type_id, payload);
+            }
+            return;
+        }
+
+        if flags & BEGIN_FLAG != 0 {
+            self.begin(producer_id, msg_type_id);
+        }
+
+        let Entry::Occupied(mut entry) = self.partials.entry(producer_id) else {
+            return;
+        };
+        let partial = entry.get_mut();
+
+        if partial.msg_type_id != msg_type_id
+            || partial.buffer.len() + payload.len() > self.max_message_length
+        {
+            entry.remove();
+            return;
+        }
+
+        partial.buffer.extend_from_slice(payload);
+
+        if flags & END_FLAG != 0 {
+            let partial = entry.remove();
+            handler(msg_type_id, &partial.buffer);
+        }
+    }
+
+    fn begin(&mut self, producer_id: i64, msg_type_id: i32) {
+        if !self.partials.contains_key(&producer_id) && self.partials.len() >= self.max_partials {
+            let oldest = self
+                .partials
+                .iter()
+                .min_by_key(|(_, partial)| partial.began)
+                .map(|(&producer_id, _)| producer_id);
+            if let Some(oldest) = oldest {
+                self.partials.remove(&oldest);
+            }
+        }
+
+        self.begin_count += 1;
+        let partial = Partial {
+            msg_type_id,
+            buffer: Vec::new(),
+            began: self.begin_count,
+        };
+        self.partials.insert(producer_id, partial);
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{FragmentAssembler, FragmentingSender, BEGIN_FLAG, END_FLAG};
+    use crate::{error::Error, RingBuffer};
+
+    fn message(seed: usize, length: usize) -> Vec<u8> {
+        (0..length).map(|i| (seed * 31 + i) as u8).collect()
+    }
+
+    #[test]
+    fn large_message_is_reassembled() {
+        let (sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+        let mut sender = FragmentingSender::new(sender).unwrap();
+        let mut assembler = FragmentAssembler::new(4096);
+        let mut received = Vec::new();
+
+        let lengths = [0, 1, 116, 117, 500, sender.max_message_length()];
+
+        for length in lengths {
+            sender.send(7, &message(length, length)).unwrap();
+            receiver.read_fragmented(
+                &mut assembler,
+                |msg_type_id, msg| received.push((msg_type_id, msg.to_vec())),
+                usize::MAX,
+            );
+        }
+
+        let expected: Vec<_> = lengths
+            .into_iter()
+            .map(|length| (7, message(length, length)))
+            .collect();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn too_long_message_is_rejected() {
+        let (sender, _receiver) = RingBuffer::new(1024).unwrap().split();
+        let mut sender = FragmentingSender::new(sender).unwrap();
+        let max_message_length = sender.max_message_length();
+
+        assert_eq!(
+            sender.send(1, &message(0, max_message_length + 1)),
+            Err(Error::MessageTooLong {
+                length: max_message_length + 1,
+                max_message_length
+            })
+        );
+    }
+
+    #[test]
+    fn full_ring_buffer_sends_nothing() {
+        let (sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+        let mut sender = FragmentingSender::new(sender).unwrap();
+
+        sender.send(1, &message(0, 600)).unwrap();
+        assert_eq!(
+            sender.send(1, &message(1, 600)),
+            Err(Error::InsufficientCapacity)
+        );
+
+        let mut assembler = FragmentAssembler::new(1024);
+        let mut count = 0;
+        receiver.read_fragmented(
+            &mut assembler,
+            |_, msg| {
+                assert_eq!(msg, message(0, 600));
+                count += 1;
+            },
+            usize::MAX,
+        );
+        assert_eq!(count, 1);
+        assert!(receiver.receive(usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn assembler_drops_oversized_and_interrupted_messages() {
+        let mut assembler = FragmentAssembler::new(8);
+        let mut received = Vec::new();
+        let mut handler = |msg_type_id: i32, msg: &[u8]| received.push((msg_type_id, msg.to_vec()));
+
+        let fragment = |producer_id: i64, flags: u8, payload: &[u8]| {
+            let mut fragment = producer_id.to_le_bytes().to_vec();
+            fragment.extend([flags, 0, 0, 0]);
+            fragment.extend(payload);
+            fragment
+        };
+
+        // Longer than the reassembly buffer.
+        assembler.on_fragment(1, &fragment(1, BEGIN_FLAG, &[1; 6]), &mut handler);
+        assembler.on_fragment(1, &fragment(1, END_FLAG, &[1; 6]), &mut handler);
+        // Begins again before the end, the first attempt is dropped.
+        assembler.on_fragment(2, &fragment(1, BEGIN_FLAG, &[2; 2]), &mut handler);
+        assembler.on_fragment(3, &fragment(1, BEGIN_FLAG, &[3; 2]), &mut handler);
+        // Interleaved with another producer.
+        assembler.on_fragment(4, &fragment(2, BEGIN_FLAG, &[4; 2]), &mut handler);
+        assembler.on_fragment(3, &fragment(1, END_FLAG, &[3; 2]), &mut handler);
+        assembler.on_fragment(4, &fragment(2, END_FLAG, &[4; 2]), &mut handler);
+
+        assert_eq!(received, [(3, vec![3; 4]), (4, vec![4; 4])]);
+    }
+
+    #[test]
+    fn assembler_releases_partials_and_caps_them() {
+        let mut assembler = FragmentAssembler::with_max_partials(8, 2);
+
+        let fragment = |producer_id: i64, flags: u8, payload: &[u8]| {
+            let mut fragment = producer_id.to_le_bytes().to_vec();
+            fragment.extend([flags, 0, 0, 0]);
+            fragment.extend(payload);
+            fragment
+        };
+
+        // Producers that come and go leave nothing behind.
+        let mut count = 0;
+        let mut handler = |_: i32, _: &[u8]| count += 1;
+        for producer_id in 0..100 {
+            assembler.on_fragment(1, &fragment(producer_id, BEGIN_FLAG, &[1; 2]), &mut handler);
+            assembler.on_fragment(1, &fragment(producer_id, END_FLAG, &[1; 2]), &mut handler);
+        }
+        assert_eq!(count, 100);
+        assert_eq!(assembler.partial_count(), 0);
+
+        // A third producer beginning drops the partial of the one that began first.
+        let mut received = Vec::new();
+        let mut handler = |msg_type_id: i32, msg: &[u8]| received.push((msg_type_id, msg.to_vec()));
+        for producer_id in 1..=3 {
+            let payload = [producer_id as u8; 2];
+            assembler.on_fragment(
+                1,
+                &fragment(producer_id, BEGIN_FLAG, &payload),
+                &mut handler,
+            );
+        }
+        assert_eq!(assembler.partial_count(), 2);
+        for producer_id in 1..=3 {
+            let payload = [producer_id as u8; 2];
+            assembler.on_fragment(1, &fragment(producer_id, END_FLAG, &payload), &mut handler);
+        }
+        assert_eq!(assembler.partial_count(), 0);
+        assert_eq!(received, [(1, vec![2; 4]), (1, vec![3; 4])]);
+    }
+
+    #[test]
+    fn interleaved_producers_are_reassembled() {
+        let (sender, mut receiver) = RingBuffer::new(4096).unwrap().split();
+        let producer_count: usize = 3;
+        let messages_per_producer: usize = 500;
+
+        std::thread::scope(|s| {
+            for producer in 0..producer_count {
+                let mut sender = FragmentingSender::new(sender.clone()).unwrap();
+                s.spawn(move || {
+                    for sequence in 0..messages_per_producer {
+                        let msg = message(sequence, sequence % 1500);
+                        while sender.send(1 + producer as i32, &msg).is_err() {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            let mut assembler = FragmentAssembler::new(2048);
+            let mut next_sequence = vec![0; producer_count];
+
+            while next_sequence.iter().any(|&n| n < messages_per_producer) {
+                let count = receiver.read_fragmented(
+                    &mut assembler,
+                    |msg_type_id, msg| {
+                        let producer = msg_type_id as usize - 1;
+                        let sequence = next_sequence[producer];
+                        assert_eq!(msg, message(sequence, sequence % 1500));
+                        next_sequence[producer] += 1;
+                    },
+                    usize::MAX,
+                );
+
+                if count == 0 {
+                    std::thread::yield_now();
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,50 @@
+//! Parking the receiver on a futex word in the ring buffer memory, see
+//! [`Receiver::receive_blocking`](crate::receiver::Receiver::receive_blocking).
+//!
+//! The word, at the start of the [`RawDescriptor`](crate::descriptor) padding, holds [`PARKED`]
+//! while the receiver is parked or about to park. After publishing a record, a sender checks the
+//! word behind a sequentially consistent fence and wakes the receiver. The receiver checks for a
+//! record after setting the word, behind the same kind of fence, so at least one of them sees the
+//! other. The futex is not process private, which makes it work for file backed ring buffers
+//! mapped by several processes.
+
+use std::{ptr, time::Duration};
+
+use crate::sync::AtomicU32;
+
+pub(crate) const PARKED: u32 = 1;
+
+/// Sleeps until woken or `timeout` elapses, unless `word` no longer holds `expected`.
+pub(crate) fn wait(word: &AtomicU32, expected: u32, timeout: Duration) {
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    };
+
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            libc::FUTEX_WAIT,
+            expected,
+            &timeout as *const libc::timespec,
+            ptr::null::<u32>(),
+            0,
+        )
+    };
+}
+
+/// Wakes the thread waiting on `word`, if any.
+pub(crate) fn wake(word: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            libc::FUTEX_WAKE,
+            1,
+            ptr::null::<libc::timespec>(),
+            ptr::null::<u32>(),
+            0,
+        )
+    };
+}
@@ -0,0 +1,353 @@
+//! Idle strategies for polling loops, following Agrona's `IdleStrategy`.
+//!
+//! A loop reports how much work each iteration did, the strategy idles in its own way while there
+//! is none:
+//!
+//! ```no_run
+//! use agrona::{idle_strategy::{BackoffIdleStrategy, IdleStrategy}, receiver::Receiver};
+//!
+//! let mut receiver = unsafe { Receiver::open_file("/dev/shm/ring-buffer") }.unwrap();
+//! let mut idle_strategy = BackoffIdleStrategy::default();
+//!
+//! loop {
+//!     let work_count = receiver.read(|msg_type_id, msg| { /* ... */ }, 10);
+//!     idle_strategy.idle(work_count);
+//! }
+//! ```
+
+use std::{hint, thread, time::Duration};
+
+use crate::counters::ReadablePosition;
+
+pub trait IdleStrategy {
+    /// Called after every iteration of the loop with the amount of work it did, idles if there was
+    /// none.
+    fn idle(&mut self, work_count: usize);
+
+    /// Forgets about past iterations, so the next idle starts over.
+    fn reset(&mut self) {}
+}
+
+impl<S: IdleStrategy + ?Sized> IdleStrategy for Box<S> {
+    fn idle(&mut self, work_count: usize) {
+        (**self).idle(work_count)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+/// Spins with a hint to the processor, for the lowest latency at the cost of a whole core.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BusySpinIdleStrategy;
+
+impl IdleStrategy for BusySpinIdleStrategy {
+    fn idle(&mut self, work_count: usize) {
+        if work_count == 0 {
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Doesn't idle at all.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoOpIdleStrategy;
+
+impl IdleStrategy for NoOpIdleStrategy {
+    fn idle(&mut self, _work_count: usize) {}
+}
+
+/// Yields the thread to the scheduler.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct YieldingIdleStrategy;
+
+impl IdleStrategy for YieldingIdleStrategy {
+    fn idle(&mut self, work_count: usize) {
+        if work_count == 0 {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Sleeps for a fixed period.
+#[derive(Debug, Clone, Copy)]
+pub struct SleepingIdleStrategy {
+    sleep_period: Duration,
+}
+
+impl SleepingIdleStrategy {
+    pub const DEFAULT_SLEEP_PERIOD: Duration = Duration::from_micros(1);
+
+    pub fn new(sleep_period: Duration) -> Self {
+        Self { sleep_period }
+    }
+}
+
+impl Default for SleepingIdleStrategy {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_SLEEP_PERIOD)
+    }
+}
+
+impl IdleStrategy for SleepingIdleStrategy {
+    fn idle(&mut self, work_count: usize) {
+        if work_count == 0 {
+            thread::sleep(self.sleep_period);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BackoffState {
+    NotIdle,
+    Spinning,
+    Yielding,
+    Parking,
+}
+
+/// Spins, then yields, then parks for periods doubling up to a maximum, for as long as there is
+/// no work. Any work starts over with spinning.
+#[derive(Debug, Clone)]
+pub struct BackoffIdleStrategy {
+    max_spins: u64,
+    max_yields: u64,
+    min_park_period: Duration,
+    max_park_period: Duration,
+    state: BackoffState,
+    spins: u64,
+    yields: u64,
+    park_period: Duration,
+}
+
+impl BackoffIdleStrategy {
+    pub const DEFAULT_MAX_SPINS: u64 = 10;
+    pub const DEFAULT_MAX_YIELDS: u64 = 5;
+    pub const DEFAULT_MIN_PARK_PERIOD: Duration = Duration::from_micros(1);
+    pub const DEFAULT_MAX_PARK_PERIOD: Duration = Duration::from_millis(1);
+
+    pub fn new(
+        max_spins: u64,
+        max_yields: u64,
+        min_park_period: Duration,
+        max_park_period: Duration,
+    ) -> Self {
+        assert!(min_park_period <= max_park_period);
+
+        Self {
+            max_spins,
+            max_yields,
+            min_park_period,
+            max_park_period,
+            state: BackoffState::NotIdle,
+            spins: 0,
+            yields: 0,
+            park_period: min_park_period,
+        }
+    }
+}
+
+impl Default for BackoffIdleStrategy {
+    fn default() -> Self {
+        Self::new(
+            Self::DEFAULT_MAX_SPINS,
+            Self::DEFAULT_MAX_YIELDS,
+            Self::DEFAULT_MIN_PARK_PERIOD,
+            Self::DEFAULT_MAX_PARK_PERIOD,
+        )
+    }
+}
+
+impl IdleStrategy for BackoffIdleStrategy {
+    fn idle(&mut self, work_count: usize) {
+        if work_count > 0 {
+            self.reset();
+            return;
+        }
+
+        match self.state {
+            BackoffState::NotIdle => {
+                self.state = BackoffState::Spinning;
+                self.spins += 1;
+            }
+            BackoffState::Spinning => {
+                hint::spin_loop();
+                self.spins += 1;
+                if self.spins > self.max_spins {
+                    self.state = BackoffState::Yielding;
+                    self.yields = 0;
+                }
+            }
+            BackoffState::Yielding => {
+                self.yields += 1;
+                if self.yields > self.max_yields {
+                    self.state = BackoffState::Parking;
+                    self.park_period = self.min_park_period;
+                } else {
+                    thread::yield_now();
+                }
+            }
+            BackoffState::Parking => {
+                thread::park_timeout(self.park_period);
+                self.park_period = (self.park_period * 2).min(self.max_park_period);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.spins = 0;
+        self.yields = 0;
+        self.park_period = self.min_park_period;
+        self.state = BackoffState::NotIdle;
+    }
+}
+
+/// Idles the way the value of a counter says, so a live process can be switched between low
+/// latency and low CPU usage by setting the counter.
+///
+/// The counter holds one of the `*_MODE` constants, any other value parks like [`Self::PARK_MODE`].
+#[derive(Debug, Clone)]
+pub struct ControllableIdleStrategy {
+    mode: ReadablePosition,
+}
+
+impl ControllableIdleStrategy {
+    pub const NOT_CONTROLLED_MODE: i64 = 0;
+    pub const NOOP_MODE: i64 = 1;
+    pub const BUSY_SPIN_MODE: i64 = 2;
+    pub const YIELD_MODE: i64 = 3;
+    pub const PARK_MODE: i64 = 4;
+
+    pub const PARK_PERIOD: Duration = Duration::from_micros(1);
+
+    pub fn new(mode: ReadablePosition) -> Self {
+        Self { mode }
+    }
+}
+
+impl IdleStrategy for ControllableIdleStrategy {
+    fn idle(&mut self, work_count: usize) {
+        if work_count > 0 {
+            return;
+        }
+
+        match self.mode.get_volatile() {
+            Self::NOOP_MODE => {}
+            Self::BUSY_SPIN_MODE => hint::spin_loop(),
+            Self::YIELD_MODE => thread::yield_now(),
+            _ => thread::park_timeout(Self::PARK_PERIOD),
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{
+        BackoffIdleStrategy, BackoffState, ControllableIdleStrategy, IdleStrategy,
+        SleepingIdleStrategy,
+    };
+    use crate::{
+        backing::Backing,
+        clock::SystemEpochClock,
+        counters::{CountersManager, COUNTER_LENGTH, METADATA_LENGTH},
+        RingBuffer,
+    };
+
+    #[test]
+    fn backoff_spins_then_yields_then_parks_longer_each_time() {
+        let mut idle_strategy =
+            BackoffIdleStrategy::new(2, 1, Duration::from_micros(1), Duration::from_micros(4));
+
+        let mut states = Vec::new();
+        for _ in 0..8 {
+            idle_strategy.idle(0);
+            states.push((idle_strategy.state, idle_strategy.park_period.as_micros()));
+        }
+
+        assert_eq!(
+            states,
+            [
+                (BackoffState::Spinning, 1),
+                (BackoffState::Spinning, 1),
+                (BackoffState::Yielding, 1),
+                (BackoffState::Yielding, 1),
+                (BackoffState::Parking, 1),
+                (BackoffState::Parking, 2),
+                (BackoffState::Parking, 4),
+                (BackoffState::Parking, 4),
+            ]
+        );
+
+        idle_strategy.idle(1);
+        assert_eq!(idle_strategy.state, BackoffState::NotIdle);
+        assert_eq!(idle_strategy.park_period, Duration::from_micros(1));
+    }
+
+    #[test]
+    fn work_skips_idling() {
+        let mut idle_strategy = SleepingIdleStrategy::new(Duration::from_secs(60));
+
+        let start = Instant::now();
+        idle_strategy.idle(1);
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn controllable_follows_its_counter() {
+        let mut manager = CountersManager::new(
+            Backing::heap(METADATA_LENGTH),
+            Backing::heap(COUNTER_LENGTH),
+            SystemEpochClock,
+            0,
+        )
+        .unwrap();
+        let mode = manager.new_counter(1, &[], "idle strategy").unwrap();
+        let mut idle_strategy =
+            ControllableIdleStrategy::new(manager.readable_position(mode.id()).unwrap());
+
+        for value in [
+            ControllableIdleStrategy::NOT_CONTROLLED_MODE,
+            ControllableIdleStrategy::NOOP_MODE,
+            ControllableIdleStrategy::BUSY_SPIN_MODE,
+            ControllableIdleStrategy::YIELD_MODE,
+            ControllableIdleStrategy::PARK_MODE,
+        ] {
+            mode.set_ordered(value);
+            assert_eq!(idle_strategy.mode.get_volatile(), value);
+            idle_strategy.idle(0);
+        }
+    }
+
+    #[test]
+    fn receive_loop_with_backoff_gets_every_message() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+        let messages: u32 = 1_000;
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                let mut idle_strategy = BackoffIdleStrategy::default();
+                for sequence in 0..messages {
+                    while sender.send(1, &sequence.to_le_bytes()).is_err() {
+                        idle_strategy.idle(0);
+                    }
+                    idle_strategy.reset();
+                }
+            });
+
+            let mut idle_strategy: Box<dyn IdleStrategy> = Box::new(BackoffIdleStrategy::default());
+            let mut next_sequence: u32 = 0;
+            while next_sequence < messages {
+                let work_count = receiver.read(
+                    |_, msg| {
+                        assert_eq!(msg, next_sequence.to_le_bytes());
+                        next_sequence += 1;
+                    },
+                    usize::MAX,
+                );
+                idle_strategy.idle(work_count);
+            }
+        });
+    }
+}
@@ -1,29 +1,46 @@
 #![allow(dead_code, unused_variables)]
 
+pub mod backing;
+pub mod broadcast;
+pub mod clock;
+pub mod counters;
 pub mod descriptor;
+pub mod error;
+pub mod error_log;
+pub mod fixed;
+pub mod fragment;
+#[cfg(all(feature = "blocking", not(loom)))]
+mod futex;
+pub mod idle_strategy;
+mod mmap;
+pub mod one_to_one;
 pub mod receiver;
 pub mod sender;
+mod sync;
+mod wakers;
 
 // #![allow(dead_code, unused_variables)]
 
-use descriptor::{Descriptor, RawDescriptor};
+use backing::Backing;
+use descriptor::{Descriptor, RawDescriptor, ReceiverDescriptor, SenderDescriptor};
+use error::Error;
 use receiver::Receiver;
 use sender::Sender;
 use std::{
-    alloc::{alloc, dealloc, Layout},
+    io,
     mem::{align_of, size_of},
+    path::Path,
     ptr::NonNull,
-    sync::{
-        atomic::{compiler_fence, AtomicI32, AtomicI64, AtomicUsize, Ordering},
-        Arc,
-    },
+    slice,
+    sync::Arc,
 };
+use sync::{AtomicI32, Ordering};
 
 // TODO: move somewhere else (aeron-client/src/main/c/util/aeron_binutil.h)
 const AERON_CACHE_LINE_LENGTH: usize = 64;
 
 const AERON_RB_TRAILER_LENGTH: usize = size_of::<RawDescriptor>();
-const AERON_RB_RECORD_HEADER_LENGTH: usize = size_of::<RecordDescriptor>();
+const AERON_RB_RECORD_HEADER_LENGTH: usize = 2 * size_of::<i32>();
 const AERON_MPSC_RB_MIN_CAPACITY: usize = AERON_RB_RECORD_HEADER_LENGTH;
 const AERON_RB_ALIGNMENT: usize = 2 * size_of::<i32>();
 const AERON_RB_PADDING_MSG_TYPE_ID: i32 = -1;
@@ -35,75 +52,165 @@ pub struct RingBuffer {
     capacity: usize,
     descriptor: Descriptor,
     max_message_length: usize,
-    reference_count: Arc<AtomicUsize>,
+    backing: Arc<Backing>,
 }
 
 impl RingBuffer {
-    pub fn new(capacity: usize) -> Result<Self, ()> {
-        let length: usize = capacity + AERON_RB_TRAILER_LENGTH;
-
-        if is_capacity_valid(capacity, AERON_MPSC_RB_MIN_CAPACITY) {
-            let layout = Layout::from_size_align(length, align_of::<RawDescriptor>()).unwrap();
-            let buffer = unsafe { alloc(layout) } as *mut u8;
-            Ok(Self {
-                buffer: NonNull::new(buffer).unwrap(),
-                descriptor: {
-                    let descriptor_ptr = unsafe { buffer.byte_add(capacity) };
-                    let descriptor = Descriptor::new(descriptor_ptr);
-                    descriptor.reset();
-                    descriptor
-                },
-                capacity,
-                max_message_length: aeron_rb_max_message_length(
-                    capacity,
-                    AERON_MPSC_RB_MIN_CAPACITY,
-                ),
-                reference_count: Arc::new(AtomicUsize::new(1)),
-            })
-        } else {
-            Err(()) // TODO: create actual error type with "Invalid capacity: {capacity}" message
+    pub fn new(capacity: usize) -> Result<Self, Error> {
+        if !is_capacity_valid(capacity, AERON_MPSC_RB_MIN_CAPACITY) {
+            return Err(Error::InvalidCapacity(capacity));
         }
+
+        // Zeroed memory holds an empty ring buffer.
+        let ring_buffer =
+            unsafe { Self::from_backing(Backing::heap(capacity + AERON_RB_TRAILER_LENGTH)) }?;
+        ring_buffer.descriptor.reset();
+
+        Ok(ring_buffer)
     }
 
-    pub unsafe fn from_memory(buffer: *mut u8, length: usize) -> Result<Self, ()> {
-        let capacity: usize = length - AERON_RB_TRAILER_LENGTH;
+    /// # Safety
+    ///
+    /// `buffer` must point to `length` bytes of memory that stay valid for as long as the ring
+    /// buffer, or any of its split halves, is alive. The memory is never freed by the ring buffer.
+    /// It must also meet the requirements of [`RingBuffer::from_backing`].
+    pub unsafe fn from_memory(buffer: *mut u8, length: usize) -> Result<Self, Error> {
+        unsafe { Self::from_backing(Backing::borrowed(buffer, length)) }
+    }
 
-        if is_capacity_valid(capacity, AERON_MPSC_RB_MIN_CAPACITY) {
-            Ok(Self {
-                buffer: NonNull::new(buffer).unwrap(),
-                descriptor: Descriptor::new(buffer.byte_add(capacity)),
-                capacity,
-                max_message_length: aeron_rb_max_message_length(
-                    capacity,
-                    AERON_MPSC_RB_MIN_CAPACITY,
-                ),
-                reference_count: Arc::new(AtomicUsize::new(2)), // This reference + original allocator
-            })
-        } else {
-            Err(()) // TODO: create actual error type with "Invalid capacity: {capacity}" message
+    /// Creates a ring buffer over `backing`.
+    ///
+    /// The backing is released once the ring buffer and all of its split halves are dropped.
+    ///
+    /// # Safety
+    ///
+    /// The memory must either be zeroed or already hold a ring buffer, and only be written by ring
+    /// buffer handles for as long as this one, or any of its split halves, is alive. The receiver
+    /// trusts the record lengths it finds, any other content can make it read out of bounds.
+    pub unsafe fn from_backing(backing: Backing) -> Result<Self, Error> {
+        #[cfg(loom)]
+        assert!(
+            backing.has_record_headers(),
+            "only heap backed ring buffers can be model checked"
+        );
+
+        let buffer = backing.as_ptr();
+        let capacity: usize = backing.len().saturating_sub(AERON_RB_TRAILER_LENGTH);
+
+        if buffer.align_offset(align_of::<RawDescriptor>()) != 0 {
+            return Err(Error::MisalignedBuffer {
+                alignment: align_of::<RawDescriptor>(),
+            });
         }
+
+        if !is_capacity_valid(capacity, AERON_MPSC_RB_MIN_CAPACITY) {
+            return Err(Error::InvalidCapacity(capacity));
+        }
+
+        Ok(Self {
+            buffer: NonNull::new(buffer).unwrap(),
+            descriptor: Descriptor::new(unsafe { buffer.byte_add(capacity) }),
+            capacity,
+            max_message_length: aeron_rb_max_message_length(capacity, AERON_MPSC_RB_MIN_CAPACITY),
+            backing: Arc::new(backing),
+        })
     }
 
-    pub fn split(self) -> (Sender, Receiver) {
-        self.reference_count.fetch_add(1, Ordering::Relaxed); // By splitting the refcount is increased by one
-        (
-            Sender {
-                buffer: self.buffer,
-                capacity: self.capacity,
-                descriptor: self.descriptor.into(),
-                max_message_length: self.max_message_length,
-                reference_count: self.reference_count.clone(),
-            },
-            Receiver {
-                buffer: self.buffer,
-                capacity: self.capacity,
-                descriptor: self.descriptor.into(),
-                _max_message_length: self.max_message_length,
-                reference_count: self.reference_count.clone(),
-            },
+    /// Creates a ring buffer with `capacity` in a new file at `path`, replacing any existing file.
+    ///
+    /// Other processes attach to it with [`RingBuffer::open_file`], [`Sender::open_file`] or
+    /// [`Receiver::open_file`].
+    pub fn create_file(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        if !is_capacity_valid(capacity, AERON_MPSC_RB_MIN_CAPACITY) {
+            return Err(Error::InvalidCapacity(capacity).into());
+        }
+
+        // A new file is zeroed, so it holds an empty ring buffer.
+        let ring_buffer = unsafe {
+            Self::from_backing(Backing::create_file(
+                path,
+                capacity + AERON_RB_TRAILER_LENGTH,
+            )?)
+        }?;
+        ring_buffer.descriptor.reset();
+
+        Ok(ring_buffer)
+    }
+
+    /// Attaches to a ring buffer created by [`RingBuffer::create_file`].
+    ///
+    /// # Safety
+    ///
+    /// The file must meet the requirements of [`RingBuffer::from_backing`].
+    pub unsafe fn open_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(unsafe { Self::from_backing(Backing::open_file(path)?) }?)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
+
+    /// Position up to which producers have claimed space, see [`RingBuffer::size`].
+    pub fn producer_position(&self) -> i64 {
+        self.descriptor.tail_position().load(Ordering::Acquire)
+    }
+
+    /// Position up to which the receiver has consumed.
+    pub fn consumer_position(&self) -> i64 {
+        self.descriptor.head_position().load(Ordering::Acquire)
+    }
+
+    /// Number of bytes currently claimed in the ring buffer, including padding and messages that
+    /// are still being written.
+    pub fn size(&self) -> usize {
+        aeron_rb_size(
+            self.capacity,
+            || self.descriptor.head_position().load(Ordering::Acquire),
+            || self.descriptor.tail_position().load(Ordering::Acquire),
         )
     }
 
+    /// Returns a correlation id that is unique among all users of the ring buffer, including other
+    /// processes attached to the same memory.
+    pub fn next_correlation_id(&self) -> i64 {
+        self.descriptor
+            .correlation_counter()
+            .fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Last time, in milliseconds since the epoch, the receiver reported it was alive.
+    pub fn consumer_heartbeat_time(&self) -> i64 {
+        self.descriptor.consumer_heartbeat().load(Ordering::Acquire)
+    }
+
+    pub fn split(self) -> (Sender, Receiver) {
+        (self.sender(), self.receiver())
+    }
+
+    pub(crate) fn sender(&self) -> Sender {
+        Sender {
+            buffer: self.buffer,
+            capacity: self.capacity,
+            descriptor: SenderDescriptor::new(self.descriptor, self.backing.wakers()),
+            max_message_length: self.max_message_length,
+            backing: self.backing.clone(),
+        }
+    }
+
+    pub(crate) fn receiver(&self) -> Receiver {
+        Receiver {
+            buffer: self.buffer,
+            capacity: self.capacity,
+            descriptor: ReceiverDescriptor::new(self.descriptor, self.backing.wakers()),
+            max_message_length: self.max_message_length,
+            backing: self.backing.clone(),
+        }
+    }
+
     // pub fn write(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), ()> {
     //     if msg.len() > self.max_message_length || aeron_rb_invalid_msg_type_id(msg_type_id) {
     //         return Err(());
@@ -187,61 +294,100 @@ impl RingBuffer {
     // }
 }
 
-impl Drop for RingBuffer {
-    fn drop(&mut self) {
-        if self.reference_count.fetch_sub(1, Ordering::Relaxed) == 0 {
-            unsafe {
-                free_buffer(self.buffer, self.capacity);
-            }
-        }
-    }
-}
-
-unsafe fn free_buffer(buffer: NonNull<u8>, capacity: usize) {
-    let length = capacity + AERON_RB_TRAILER_LENGTH;
-    let layout = Layout::from_size_align(length, align_of::<RawDescriptor>())
-        .expect("expect to create the same layout as used for allocation");
-    unsafe { dealloc(buffer.as_ptr(), layout) };
-}
-
 #[repr(C, align(4))]
 struct RecordDescriptor {
     length: AtomicI32,
     msg_type_id: AtomicI32,
+    /// Stands in for the message bytes of the record, so loom checks the accesses to them.
+    #[cfg(loom)]
+    message: loom::cell::UnsafeCell<()>,
 }
 
-// TODO: move somewhere else
-// Most likely wrong, should use atomic
-fn aeron_get_volatile_i32(dst: &mut i32, src: &AtomicI32) {
-    *dst = src.load(Ordering::Relaxed);
-    compiler_fence(std::sync::atomic::Ordering::SeqCst);
+#[cfg(loom)]
+impl RecordDescriptor {
+    fn new() -> Self {
+        Self {
+            length: AtomicI32::new(0),
+            msg_type_id: AtomicI32::new(0),
+            message: loom::cell::UnsafeCell::new(()),
+        }
+    }
 }
 
-// TODO: move somewhere else
-// Most likely wrong, should use atomic
-fn aeron_get_volatile_i64(dst: &mut i64, src: &AtomicI64) {
-    *dst = src.load(Ordering::Relaxed);
-    compiler_fence(std::sync::atomic::Ordering::SeqCst);
+#[cfg(not(loom))]
+const _: () = assert!(size_of::<RecordDescriptor>() == AERON_RB_RECORD_HEADER_LENGTH);
+
+/// # Safety
+///
+/// `index` must be the aligned start of a record within the `capacity` bytes of `buffer`.
+#[cfg(not(loom))]
+unsafe fn record_header<'a>(buffer: NonNull<u8>, index: usize) -> &'a RecordDescriptor {
+    let ptr: *mut u8 = unsafe { buffer.as_ptr().byte_add(index) };
+    unsafe { &*(ptr as *const RecordDescriptor) }
 }
 
-// TODO: move somewhere else
-// Most likely wrong, should use atomic
-fn aeron_put_ordered_i64(dst: &AtomicI64, src: i64) {
-    compiler_fence(std::sync::atomic::Ordering::SeqCst);
-    dst.store(src, Ordering::Relaxed);
+/// Loom atomics don't fit in the buffer, so the headers live aside in one slot per alignment step,
+/// see [`Backing::heap`].
+#[cfg(loom)]
+unsafe fn record_header<'a>(buffer: NonNull<u8>, index: usize) -> &'a RecordDescriptor {
+    let headers = unsafe { *(buffer.as_ptr() as *const *const RecordDescriptor).sub(1) };
+    unsafe { &*headers.add(index / AERON_RB_ALIGNMENT) }
 }
 
-// TODO: move somewhere else
-// Most likely wrong, should use atomic
-fn aeron_put_ordered_i32(dst: &AtomicI32, src: i32) {
-    compiler_fence(std::sync::atomic::Ordering::SeqCst);
-    dst.store(src, Ordering::Relaxed);
+/// The `length` message bytes of the record at `record_index`, to be filled by a producer.
+///
+/// # Safety
+///
+/// The record must be claimed by the caller.
+unsafe fn message_mut<'a>(buffer: NonNull<u8>, record_index: usize, length: usize) -> &'a mut [u8] {
+    #[cfg(loom)]
+    unsafe { record_header(buffer, record_index) }
+        .message
+        .with_mut(|_| ());
+
+    let ptr = unsafe {
+        buffer
+            .as_ptr()
+            .byte_add(aeron_rb_message_offset(record_index))
+    };
+    unsafe { slice::from_raw_parts_mut(ptr, length) }
+}
+
+/// The `length` message bytes of the record at `record_index`, to be read by the receiver.
+///
+/// # Safety
+///
+/// The record must be published and not yet consumed.
+unsafe fn message<'a>(buffer: NonNull<u8>, record_index: usize, length: usize) -> &'a [u8] {
+    #[cfg(loom)]
+    unsafe { record_header(buffer, record_index) }
+        .message
+        .with(|_| ());
+
+    let ptr = unsafe {
+        buffer
+            .as_ptr()
+            .byte_add(aeron_rb_message_offset(record_index))
+    };
+    unsafe { slice::from_raw_parts(ptr, length) }
 }
 
-// fn aeron_cas_i64(dst: &AtomicI64, expected: i64, desired: i64) -> bool {
-//     let original = dst.compare_and_swap(expected, desired, Ordering::SeqCst);
-//     original == expected
-// }
+/// Zeroes `length` bytes of consumed records starting at `index`.
+///
+/// # Safety
+///
+/// The records must be consumed by the caller and the head not yet moved past them.
+unsafe fn zero_records(buffer: NonNull<u8>, index: usize, length: usize) {
+    unsafe { buffer.as_ptr().byte_add(index).write_bytes(0, length) };
+
+    #[cfg(loom)]
+    for index in (index..index + length).step_by(AERON_RB_ALIGNMENT) {
+        let header = unsafe { record_header(buffer, index) };
+        header.length.store(0, Ordering::Relaxed);
+        header.msg_type_id.store(0, Ordering::Relaxed);
+        header.message.with_mut(|_| ());
+    }
+}
 
 // TODO: move somewhere else
 fn is_capacity_valid(capacity: usize, min_capacity: usize) -> bool {
@@ -261,16 +407,45 @@ const fn aeron_rb_invalid_msg_type_id(id: i32) -> bool {
 }
 
 fn aeron_rb_message_offset(index: usize) -> usize {
-    index + size_of::<RecordDescriptor>()
+    index + AERON_RB_RECORD_HEADER_LENGTH
+}
+
+/// Reloads the head until it is unchanged around the tail load, so the result is a consistent
+/// snapshot even while the receiver is consuming.
+fn aeron_rb_size(
+    capacity: usize,
+    head_position: impl Fn() -> i64,
+    tail_position: impl Fn() -> i64,
+) -> usize {
+    let mut head_after: i64 = head_position();
+    loop {
+        let head_before: i64 = head_after;
+        let tail: i64 = tail_position();
+        head_after = head_position();
+
+        if head_after == head_before {
+            return (tail - head_after).clamp(0, capacity as i64) as usize;
+        }
+    }
 }
 
 fn aeron_align(value: usize, alignment: usize) -> usize {
     (value + (alignment - 1)) & !(alignment - 1)
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
-    use super::RingBuffer;
+    use std::{io::IoSlice, sync::atomic::Ordering};
+
+    use super::{
+        clock::{CachedEpochClock, EpochClock},
+        error::Error,
+        receiver::{ControlledReadAction, Receiver},
+        sender::Sender,
+        RingBuffer, AERON_RB_TRAILER_LENGTH,
+    };
+    #[cfg(feature = "async")]
+    use super::{fixed, one_to_one};
 
     #[test]
     fn read_write_read_single_message() {
@@ -318,6 +493,434 @@ mod tests {
         assert_eq!(received_message.1, message_two.1);
     }
 
+    #[test]
+    fn try_claim_commit_single_message() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        let mut claim = sender.try_claim(88, 5).unwrap();
+        claim.copy_from_slice(&[54, 33, 77, 11, 123]);
+
+        assert!(receiver.receive(1).is_empty());
+
+        claim.commit();
+
+        let mut received = receiver.receive(1);
+
+        assert_eq!(received.len(), 1);
+
+        let received_message = received.remove(0);
+        assert_eq!(received_message.0, 88);
+        assert_eq!(received_message.1, [54, 33, 77, 11, 123]);
+    }
+
+    #[test]
+    fn try_claim_abort_and_drop_are_skipped() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        sender.try_claim(88, 5).unwrap().abort();
+        drop(sender.try_claim(89, 3).unwrap());
+        sender.send(94, &[44, 11]).unwrap();
+
+        let mut received = receiver.receive(10);
+
+        assert_eq!(received.len(), 1);
+
+        let received_message = received.remove(0);
+        assert_eq!(received_message.0, 94);
+        assert_eq!(received_message.1, [44, 11]);
+    }
+
+    #[test]
+    fn try_claim_rejects_invalid_claims() {
+        let (mut sender, _receiver) = RingBuffer::new(1024).unwrap().split();
+
+        assert_eq!(
+            sender.try_claim(0, 5).err(),
+            Some(Error::InvalidMsgTypeId(0))
+        );
+        assert_eq!(
+            sender.try_claim(88, 1024 / 8 + 1).err(),
+            Some(Error::MessageTooLong {
+                length: 1024 / 8 + 1,
+                max_message_length: 1024 / 8
+            })
+        );
+    }
+
+    #[test]
+    fn send_vectored_concatenates_parts() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        let header = [1, 2, 3];
+        let body = [4, 5, 6, 7, 8];
+        sender
+            .send_vectored(
+                88,
+                &[
+                    IoSlice::new(&header),
+                    IoSlice::new(&[]),
+                    IoSlice::new(&body),
+                ],
+            )
+            .unwrap();
+        sender.send_vectored(89, &[]).unwrap();
+
+        assert_eq!(
+            receiver.receive(10),
+            [(88, vec![1, 2, 3, 4, 5, 6, 7, 8]), (89, vec![])]
+        );
+    }
+
+    #[test]
+    fn send_vectored_checks_total_length() {
+        let (mut sender, _receiver) = RingBuffer::new(1024).unwrap().split();
+
+        let part = [0; 100];
+        assert_eq!(
+            sender.send_vectored(88, &[IoSlice::new(&part), IoSlice::new(&part[..29])]),
+            Err(Error::MessageTooLong {
+                length: 129,
+                max_message_length: 128
+            })
+        );
+        assert_eq!(sender.size(), 0);
+    }
+
+    #[test]
+    fn send_batch_is_received_in_order() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+        let messages: Vec<(i32, Vec<u8>)> =
+            (1..=10).map(|i| (i, vec![i as u8; i as usize])).collect();
+
+        // Starts close to the end of the buffer so the batch has to wrap.
+        for _ in 0..7 {
+            sender.send(1, &[0; 128]).unwrap();
+        }
+        assert_eq!(receiver.receive(10).len(), 7);
+
+        let batch: Vec<(i32, &[u8])> = messages.iter().map(|(id, msg)| (*id, &msg[..])).collect();
+        assert_eq!(sender.send_batch(&batch), Ok(10));
+        assert_eq!(sender.send_batch(&[]), Ok(0));
+
+        let mut received = receiver.receive(usize::MAX);
+        received.extend(receiver.receive(usize::MAX));
+        assert_eq!(received, messages);
+        assert_eq!(receiver.consumer_position(), 7 * 136 + 72 + 176);
+    }
+
+    #[test]
+    fn send_batch_sends_all_or_nothing() {
+        let (mut sender, _receiver) = RingBuffer::new(1024).unwrap().split();
+
+        assert_eq!(
+            sender.send_batch(&[(1, &[0; 8]), (0, &[0; 8])]),
+            Err(Error::InvalidMsgTypeId(0))
+        );
+        assert_eq!(
+            sender.send_batch(&[(1, [0; 100].as_slice()); 10]),
+            Err(Error::BatchTooLong {
+                length: 1120,
+                max_batch_length: 512
+            })
+        );
+        for _ in 0..5 {
+            sender.send(1, &[0; 128]).unwrap();
+        }
+        assert_eq!(
+            sender.send_batch(&[(1, [0; 100].as_slice()); 4]),
+            Err(Error::InsufficientCapacity)
+        );
+        assert_eq!(sender.size(), 5 * 136);
+    }
+
+    #[test]
+    fn send_batch_longer_than_half_the_capacity_is_too_long_anywhere() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        // Leaves the tail mid-ring, where 512 bytes fit before the end and 512 after the start.
+        sender.send_batch(&[(1, [0; 128].as_slice()); 3]).unwrap();
+        sender.send(1, &[0; 96]).unwrap();
+        assert_eq!(receiver.receive(usize::MAX).len(), 4);
+        assert_eq!(sender.size(), 0);
+
+        let mut batch: [(i32, &[u8]); 4] = [(1, &[0; 128]); 4];
+        batch[3].1 = &[0; 104];
+        assert_eq!(
+            sender.send_batch(&batch),
+            Err(Error::BatchTooLong {
+                length: 520,
+                max_batch_length: 512
+            })
+        );
+
+        batch[3].1 = &[0; 96];
+        assert_eq!(sender.send_batch(&batch), Ok(4));
+        assert_eq!(sender.producer_position(), 1024);
+    }
+
+    #[test]
+    fn new_rejects_invalid_capacity() {
+        assert_eq!(
+            RingBuffer::new(1000).err(),
+            Some(Error::InvalidCapacity(1000))
+        );
+        assert_eq!(RingBuffer::new(4).err(), Some(Error::InvalidCapacity(4)));
+    }
+
+    #[test]
+    fn from_memory_rejects_misaligned_buffer() {
+        let mut memory = vec![0u64; (1024 + AERON_RB_TRAILER_LENGTH) / 8 + 1];
+        let buffer = unsafe { (memory.as_mut_ptr() as *mut u8).add(4) };
+
+        assert_eq!(
+            unsafe { RingBuffer::from_memory(buffer, 1024 + AERON_RB_TRAILER_LENGTH) }.err(),
+            Some(Error::MisalignedBuffer { alignment: 8 })
+        );
+        assert_eq!(
+            unsafe { RingBuffer::from_memory(buffer, 16) }.err(),
+            Some(Error::MisalignedBuffer { alignment: 8 })
+        );
+    }
+
+    #[test]
+    fn send_reports_insufficient_capacity_until_received() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        let message = [7u8; 120];
+        for _ in 0..8 {
+            sender.send(88, &message).unwrap();
+        }
+
+        let error = sender.send(88, &message).unwrap_err();
+        assert_eq!(error, Error::InsufficientCapacity);
+        assert!(error.is_retryable());
+        assert!(!Error::InvalidCapacity(1000).is_retryable());
+
+        assert_eq!(receiver.receive(1).len(), 1);
+        sender.send(88, &message).unwrap();
+    }
+
+    #[test]
+    fn read_hands_out_messages_in_order() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        sender.send(88, &[54, 33, 77]).unwrap();
+        sender.send(94, &[44, 11]).unwrap();
+        sender.send(97, &[1]).unwrap();
+
+        let mut received = Vec::new();
+        let count = receiver.read(
+            |msg_type_id, data| received.push((msg_type_id, data.to_vec())),
+            2,
+        );
+
+        assert_eq!(count, 2);
+        assert_eq!(received, [(88, vec![54, 33, 77]), (94, vec![44, 11])]);
+
+        let count = receiver.read(
+            |msg_type_id, data| received.push((msg_type_id, data.to_vec())),
+            2,
+        );
+
+        assert_eq!(count, 1);
+        assert_eq!(received[2], (97, vec![1]));
+    }
+
+    #[test]
+    fn controlled_read_abort_leaves_message_unconsumed() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        sender.send(88, &[54, 33, 77]).unwrap();
+        sender.send(94, &[44, 11]).unwrap();
+
+        let mut seen = Vec::new();
+        let count = receiver.controlled_read(
+            |msg_type_id, _| {
+                seen.push(msg_type_id);
+                if msg_type_id == 94 {
+                    ControlledReadAction::Abort
+                } else {
+                    ControlledReadAction::Continue
+                }
+            },
+            10,
+        );
+
+        assert_eq!(count, 1);
+        assert_eq!(seen, [88, 94]);
+
+        let received = receiver.receive(10);
+
+        assert_eq!(received, [(94, vec![44, 11])]);
+    }
+
+    #[test]
+    fn controlled_read_break_stops_after_message() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        sender.send(88, &[54, 33, 77]).unwrap();
+        sender.send(94, &[44, 11]).unwrap();
+
+        let count = receiver.controlled_read(|_, _| ControlledReadAction::Break, 10);
+
+        assert_eq!(count, 1);
+        assert_eq!(receiver.receive(10), [(94, vec![44, 11])]);
+    }
+
+    #[test]
+    fn controlled_read_commit_moves_head_mid_batch() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        sender.send(88, &[54, 33, 77]).unwrap();
+        sender.send(94, &[44, 11]).unwrap();
+        sender.send(97, &[1]).unwrap();
+
+        let mut head_positions = Vec::new();
+        let count = receiver.controlled_read(
+            |msg_type_id, _| {
+                let head = sender
+                    .descriptor
+                    .head_position
+                    .load_atomic(Ordering::Relaxed);
+                head_positions.push(head);
+                match msg_type_id {
+                    88 => ControlledReadAction::Commit,
+                    94 => ControlledReadAction::Continue,
+                    _ => ControlledReadAction::Abort,
+                }
+            },
+            10,
+        );
+
+        assert_eq!(count, 2);
+        assert_eq!(head_positions, [0, 16, 16]);
+        assert_eq!(
+            sender
+                .descriptor
+                .head_position
+                .load_atomic(Ordering::Relaxed),
+            32
+        );
+        assert_eq!(receiver.receive(10), [(97, vec![1])]);
+    }
+
+    #[test]
+    fn poll_borrows_messages_until_dropped() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        sender.send(1, &[1]).unwrap();
+        sender.try_claim(2, 2).unwrap().abort();
+        sender.send(3, &[3, 3, 3]).unwrap();
+        sender.send(4, &[4; 4]).unwrap();
+
+        let batch = receiver.poll(2);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(
+            batch.iter().collect::<Vec<_>>(),
+            [(1, [1].as_slice()), (3, &[3, 3, 3])]
+        );
+        // Nothing is consumed until the batch is gone.
+        assert_eq!(sender.size(), 64);
+        let (msg_type_id, data) = (&batch).into_iter().last().unwrap();
+        assert_eq!((msg_type_id, data), (3, [3, 3, 3].as_slice()));
+        batch.commit();
+        assert_eq!(sender.size(), 16);
+
+        let batch = receiver.poll(usize::MAX);
+        assert_eq!(batch.iter().collect::<Vec<_>>(), [(4, [4; 4].as_slice())]);
+        drop(batch);
+
+        let batch = receiver.poll(usize::MAX);
+        assert!(batch.is_empty());
+        assert_eq!(batch.iter().count(), 0);
+        drop(batch);
+        assert_eq!(sender.size(), 0);
+    }
+
+    /// Fills the buffer up to 72 bytes before its end and consumes it.
+    fn near_end_of_buffer() -> (Sender, Receiver) {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        for _ in 0..7 {
+            sender.send(1, &[0; 128]).unwrap();
+        }
+        assert_eq!(receiver.receive(10).len(), 7);
+
+        (sender, receiver)
+    }
+
+    #[test]
+    fn receive_continues_past_the_wrap() {
+        let (mut sender, mut receiver) = near_end_of_buffer();
+
+        // Four records fit before the end, the rest follow the padding at the start.
+        for i in 0..10 {
+            sender.send(1 + i, &[i as u8; 8]).unwrap();
+        }
+
+        let received = receiver.receive(10);
+        assert_eq!(
+            received,
+            (0..10)
+                .map(|i| (1 + i, vec![i as u8; 8]))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(receiver.consumer_position(), 1024 + 6 * 16);
+        assert_eq!(sender.size(), 0);
+        // Both segments were zeroed for the producers.
+        assert!(receiver.receive(10).is_empty());
+        sender.send(1, &[1; 8]).unwrap();
+        assert_eq!(receiver.receive(10), [(1, vec![1; 8])]);
+    }
+
+    #[test]
+    fn controlled_read_aborts_after_the_wrap() {
+        let (mut sender, mut receiver) = near_end_of_buffer();
+
+        for i in 0..6 {
+            sender.send(1 + i, &[i as u8; 8]).unwrap();
+        }
+
+        let mut read = Vec::new();
+        let count = receiver.controlled_read(
+            |msg_type_id, _| {
+                if msg_type_id == 6 {
+                    return ControlledReadAction::Abort;
+                }
+                read.push(msg_type_id);
+                ControlledReadAction::Continue
+            },
+            usize::MAX,
+        );
+
+        assert_eq!((count, read), (5, vec![1, 2, 3, 4, 5]));
+        assert_eq!(receiver.consumer_position(), 1024 + 16);
+        assert_eq!(receiver.receive(10), [(6, vec![5; 8])]);
+    }
+
+    #[test]
+    fn poll_continues_past_the_wrap() {
+        let (mut sender, mut receiver) = near_end_of_buffer();
+
+        for i in 0..6 {
+            sender.send(1 + i, &[i as u8; 8]).unwrap();
+        }
+
+        let batch = receiver.poll(usize::MAX);
+        assert_eq!(
+            batch
+                .iter()
+                .map(|(msg_type_id, _)| msg_type_id)
+                .collect::<Vec<_>>(),
+            [1, 2, 3, 4, 5, 6]
+        );
+        drop(batch);
+
+        assert_eq!(receiver.consumer_position(), 1024 + 2 * 16);
+        assert_eq!(sender.size(), 0);
+        assert!(receiver.receive(10).is_empty());
+    }
+
     #[test]
     fn write_read_single_message_multithread() {
         std::thread::scope(|s| {
@@ -341,4 +944,587 @@ mod tests {
             });
         })
     }
+
+    #[test]
+    fn introspection_tracks_positions_and_size() {
+        let ring_buffer = RingBuffer::new(1024).unwrap();
+
+        assert_eq!(ring_buffer.capacity(), 1024);
+        assert_eq!(ring_buffer.max_message_length(), 128);
+        assert_eq!(ring_buffer.size(), 0);
+
+        let (mut sender, mut receiver) = ring_buffer.split();
+
+        sender.send(88, &[54, 33, 77]).unwrap();
+        sender.send(94, &[44, 11]).unwrap();
+
+        assert_eq!(sender.producer_position(), 32);
+        assert_eq!(receiver.producer_position(), 32);
+        assert_eq!(sender.consumer_position(), 0);
+        assert_eq!(sender.size(), 32);
+        assert_eq!(receiver.size(), 32);
+
+        receiver.receive(1);
+
+        assert_eq!(receiver.consumer_position(), 16);
+        assert_eq!(sender.size(), 16);
+        assert_eq!(receiver.capacity(), sender.capacity());
+        assert_eq!(receiver.max_message_length(), sender.max_message_length());
+    }
+
+    #[test]
+    fn correlation_ids_are_unique_across_handles() {
+        let ring_buffer = RingBuffer::new(1024).unwrap();
+
+        assert_eq!(ring_buffer.next_correlation_id(), 0);
+
+        let (sender, receiver) = ring_buffer.split();
+
+        assert_eq!(receiver.next_correlation_id(), 1);
+
+        let mut ids: Vec<i64> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let sender = sender.clone();
+                    s.spawn(move || {
+                        (0..1000)
+                            .map(|_| sender.next_correlation_id())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        ids.sort_unstable();
+        assert_eq!(ids, (2..4002).collect::<Vec<_>>());
+        assert_eq!(sender.next_correlation_id(), 4002);
+    }
+
+    #[test]
+    fn consumer_liveness_follows_heartbeat() {
+        let clock = CachedEpochClock::new(1_000);
+        let (sender, receiver) = RingBuffer::new(1024).unwrap().split();
+
+        assert_eq!(sender.consumer_heartbeat_time(), 0);
+        assert!(!sender.is_consumer_alive(&clock, 500));
+
+        receiver.update_heartbeat(clock.time());
+
+        assert_eq!(sender.consumer_heartbeat_time(), 1_000);
+        assert!(sender.is_consumer_alive(&clock, 500));
+
+        clock.advance(500);
+        assert!(sender.is_consumer_alive(&clock, 500));
+
+        clock.advance(1);
+        assert!(!sender.is_consumer_alive(&clock, 500));
+
+        receiver.update_heartbeat(clock.time());
+        assert!(sender.is_consumer_alive(&clock, 500));
+    }
+
+    #[test]
+    fn unblock_skips_claim_of_dead_producer() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        assert!(!receiver.unblock());
+
+        // A producer that dies holding a claim never commits or aborts it.
+        std::mem::forget(sender.try_claim(88, 5).unwrap());
+        sender.send(94, &[44, 11]).unwrap();
+
+        assert!(receiver.receive(10).is_empty());
+        assert!(receiver.unblock());
+        assert_eq!(receiver.receive(10), [(94, vec![44, 11])]);
+        assert!(!receiver.unblock());
+    }
+
+    #[test]
+    fn unblock_skips_space_of_producer_dead_before_writing_header() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        // A producer that dies right after moving the tail leaves the record zeroed.
+        assert!(sender
+            .descriptor
+            .tail_position
+            .cas(0, 24, Ordering::Relaxed, Ordering::Relaxed));
+        sender.send(94, &[44, 11]).unwrap();
+
+        assert!(receiver.receive(10).is_empty());
+        assert!(receiver.unblock());
+        assert_eq!(receiver.receive(10), [(94, vec![44, 11])]);
+        assert_eq!(receiver.consumer_position(), 40);
+    }
+
+    #[test]
+    fn unblock_waits_for_next_record() {
+        let (sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        // Without a following record the size of the stuck region is unknown.
+        assert!(sender
+            .descriptor
+            .tail_position
+            .cas(0, 24, Ordering::Relaxed, Ordering::Relaxed));
+
+        assert!(!receiver.unblock());
+    }
+
+    /// Polls `future` on the current thread, failing if it isn't woken within a few seconds of
+    /// returning pending.
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::{
+            sync::{atomic::AtomicBool, Arc},
+            task::{Context, Poll, Wake, Waker},
+            thread::{self, Thread},
+            time::{Duration, Instant},
+        };
+
+        struct ThreadWaker(Thread, AtomicBool);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.1.store(true, Ordering::Release);
+                self.0.unpark();
+            }
+        }
+
+        let thread_waker = Arc::new(ThreadWaker(thread::current(), AtomicBool::new(false)));
+        let waker = Waker::from(thread_waker.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !thread_waker.1.swap(false, Ordering::Acquire) {
+                assert!(Instant::now() < deadline, "future was never woken");
+                thread::park_timeout(Duration::from_millis(100));
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn next(receiver: &mut Receiver) -> (i32, bytes::Bytes) {
+        use futures_core::Stream;
+
+        block_on(std::future::poll_fn(|cx| {
+            std::pin::Pin::new(&mut *receiver).poll_next(cx)
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn stream_is_woken_by_send() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                sender.send(5, &[1, 2, 3]).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                let mut claim = sender.try_claim(6, 1).unwrap();
+                claim[0] = 4;
+                claim.commit();
+            });
+
+            assert_eq!(
+                next(&mut receiver),
+                (5, bytes::Bytes::from_static(&[1, 2, 3]))
+            );
+            assert_eq!(next(&mut receiver), (6, bytes::Bytes::from_static(&[4])));
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn send_async_waits_for_capacity() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+        let msg = [7; 120];
+
+        while sender.send(1, &msg).is_ok() {}
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                assert_eq!(receiver.receive(1), [(1, msg.to_vec())]);
+            });
+
+            block_on(sender.send_async(2, &msg)).unwrap();
+        });
+
+        assert_eq!(
+            block_on(sender.send_async(-1, &msg)),
+            Err(Error::InvalidMsgTypeId(-1))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn one_to_one_and_fixed_send_async_wait_for_capacity() {
+        let msg = [7; 120];
+
+        let (mut sender, mut receiver) = one_to_one::OneToOneRingBuffer::new(1024).unwrap().split();
+        while sender.send(1, &msg).is_ok() {}
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                assert_eq!(receiver.receive(1), [(1, msg.to_vec())]);
+            });
+
+            block_on(sender.send_async(2, &msg)).unwrap();
+        });
+
+        let mut ring_buffer = fixed::RingBuffer::<1024>::new();
+        let (mut sender, mut receiver) = ring_buffer.split();
+        while sender.send(1, &msg).is_ok() {}
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                assert_eq!(receiver.receive(1), [(1, msg.to_vec())]);
+            });
+
+            block_on(sender.send_async(2, &msg)).unwrap();
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn async_halves_miss_no_wakeup() {
+        let (mut sender, mut receiver) = RingBuffer::new(256).unwrap().split();
+        let messages: u32 = 2_000;
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for sequence in 0..messages {
+                    block_on(sender.send_async(1, &sequence.to_le_bytes())).unwrap();
+                }
+            });
+
+            for sequence in 0..messages {
+                assert_eq!(next(&mut receiver).1, sequence.to_le_bytes()[..]);
+            }
+        });
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("agrona-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn file_backed_sender_and_receiver_attach_separately() {
+        let path = temp_path("attach");
+        let ring_buffer = RingBuffer::create_file(&path, 1024).unwrap();
+
+        let mut sender = unsafe { Sender::open_file(&path) }.unwrap();
+        let mut receiver = unsafe { Receiver::open_file(&path) }.unwrap();
+        drop(ring_buffer);
+
+        sender.send(88, &[54, 33, 77, 11, 123]).unwrap();
+        drop(sender);
+
+        assert_eq!(receiver.receive(10), [(88, vec![54, 33, 77, 11, 123])]);
+
+        let (mut sender, _) = unsafe { RingBuffer::open_file(&path) }.unwrap().split();
+        sender.send(94, &[44, 11]).unwrap();
+
+        assert_eq!(receiver.receive(10), [(94, vec![44, 11])]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn open_file_rejects_invalid_length() {
+        let path = temp_path("invalid-length");
+        std::fs::write(&path, vec![0u8; 1000 + AERON_RB_TRAILER_LENGTH]).unwrap();
+
+        let error = unsafe { RingBuffer::open_file(&path) }.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.into_inner().unwrap().downcast::<Error>().unwrap(),
+            Box::new(Error::InvalidCapacity(1000))
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sender_new_attaches_without_freeing_memory() {
+        let mut memory = vec![0u64; (1024 + AERON_RB_TRAILER_LENGTH) / 8];
+        let buffer = memory.as_mut_ptr() as *mut u8;
+        let length = 1024 + AERON_RB_TRAILER_LENGTH;
+
+        let mut sender = unsafe { Sender::new(buffer, length) }.unwrap();
+        let mut receiver = unsafe { Receiver::new(buffer, length) }.unwrap();
+
+        sender.send(88, &[54, 33, 77]).unwrap();
+        drop(sender);
+
+        assert_eq!(receiver.receive(10), [(88, vec![54, 33, 77])]);
+        drop(receiver);
+
+        // Still owned by the vec, which frees it here.
+        drop(memory);
+    }
+
+    #[test]
+    #[cfg(feature = "blocking")]
+    fn receive_blocking_times_out_when_empty() {
+        let (_sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+
+        let start = std::time::Instant::now();
+        assert!(receiver
+            .receive_blocking(10, std::time::Duration::from_millis(50))
+            .is_empty());
+        assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+    }
+
+    #[test]
+    #[cfg(feature = "blocking")]
+    fn receive_blocking_is_woken_through_another_mapping() {
+        let path = temp_path("blocking");
+        let ring_buffer = RingBuffer::create_file(&path, 1024).unwrap();
+        let mut receiver = unsafe { Receiver::open_file(&path) }.unwrap();
+        let timeout = std::time::Duration::from_secs(10);
+
+        let start = std::time::Instant::now();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut sender = unsafe { Sender::open_file(&path) }.unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                sender.send(88, &[1, 2, 3]).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                let mut claim = sender.try_claim(89, 1).unwrap();
+                claim[0] = 4;
+                claim.commit();
+            });
+
+            assert_eq!(
+                receiver.receive_blocking(10, timeout),
+                [(88, vec![1, 2, 3])]
+            );
+            assert_eq!(receiver.receive_blocking(10, timeout), [(89, vec![4])]);
+        });
+        assert!(start.elapsed() < timeout);
+
+        drop((ring_buffer, receiver));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "blocking")]
+    fn receive_blocking_misses_no_wakeup() {
+        let (mut sender, mut receiver) = RingBuffer::new(1024).unwrap().split();
+        let messages: u32 = 2_000;
+        let timeout = std::time::Duration::from_secs(10);
+
+        let start = std::time::Instant::now();
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for sequence in 0..messages {
+                    while sender.send(1, &sequence.to_le_bytes()).is_err() {
+                        std::thread::yield_now();
+                    }
+                    if sequence % 2 == 0 {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+
+            let mut next_sequence: u32 = 0;
+            while next_sequence < messages {
+                for (_, data) in receiver.receive_blocking(usize::MAX, timeout) {
+                    assert_eq!(data, next_sequence.to_le_bytes());
+                    next_sequence += 1;
+                }
+            }
+        });
+        assert!(start.elapsed() < timeout);
+    }
+
+    fn multi_producer_stress(producer_count: usize, messages_per_producer: u64, capacity: usize) {
+        let (sender, mut receiver) = RingBuffer::new(capacity).unwrap().split();
+
+        std::thread::scope(|s| {
+            for producer in 0..producer_count {
+                let mut sender = sender.clone();
+                s.spawn(move || {
+                    for sequence in 0..messages_per_producer {
+                        let mut msg = [0u8; 12];
+                        msg[..4].copy_from_slice(&(producer as u32).to_le_bytes());
+                        msg[4..].copy_from_slice(&sequence.to_le_bytes());
+
+                        while sender.send(1 + producer as i32, &msg).is_err() {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            let mut next_sequence = vec![0u64; producer_count];
+            let mut remaining = producer_count as u64 * messages_per_producer;
+
+            while remaining > 0 {
+                let count = receiver.read(
+                    |msg_type_id, data| {
+                        let producer = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+                        let sequence = u64::from_le_bytes(data[4..].try_into().unwrap());
+
+                        assert_eq!(msg_type_id, 1 + producer as i32);
+                        assert_eq!(sequence, next_sequence[producer]);
+                        next_sequence[producer] += 1;
+                    },
+                    usize::MAX,
+                );
+
+                if count == 0 {
+                    std::thread::yield_now();
+                }
+                remaining -= count as u64;
+            }
+
+            assert!(next_sequence.iter().all(|&n| n == messages_per_producer));
+        });
+
+        assert!(receiver.receive(usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn multi_producer_messages_arrive_once_and_in_order() {
+        multi_producer_stress(4, 50_000, 64 * 1024);
+    }
+
+    #[test]
+    fn multi_producer_small_buffer_wraps() {
+        multi_producer_stress(8, 10_000, 1024);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::thread;
+
+    use super::{fixed, one_to_one::OneToOneRingBuffer, RingBuffer};
+
+    #[test]
+    fn message_is_received_whole() {
+        loom::model(|| {
+            let (mut sender, mut receiver) = RingBuffer::new(64).unwrap().split();
+
+            let producer = thread::spawn(move || sender.send(7, &[1, 2, 3, 4]).unwrap());
+
+            let received = receiver.receive(1);
+            producer.join().unwrap();
+            let received = [received, receiver.receive(1)].concat();
+
+            assert_eq!(received, vec![(7, vec![1, 2, 3, 4])]);
+        });
+    }
+
+    #[test]
+    fn fixed_message_is_received_whole() {
+        loom::model(|| {
+            let ring_buffer = loom::sync::Arc::new(fixed::RingBuffer::<64>::new());
+            let mut receiver = ring_buffer.receiver().unwrap();
+
+            let producer = thread::spawn({
+                let ring_buffer = ring_buffer.clone();
+                move || ring_buffer.sender().send(7, &[1, 2, 3, 4]).unwrap()
+            });
+
+            let received = receiver.receive(1);
+            producer.join().unwrap();
+            let received = [received, receiver.receive(1)].concat();
+
+            assert_eq!(received, vec![(7, vec![1, 2, 3, 4])]);
+        });
+    }
+
+    #[test]
+    fn producers_claim_distinct_records() {
+        loom::model(|| {
+            let (sender, mut receiver) = RingBuffer::new(64).unwrap().split();
+
+            let producers: Vec<_> = (1..=2)
+                .map(|id| {
+                    let mut sender = sender.clone();
+                    thread::spawn(move || sender.send(id, &[id as u8; 4]).unwrap())
+                })
+                .collect();
+
+            let mut received = receiver.receive(2);
+            for producer in producers {
+                producer.join().unwrap();
+            }
+            received.extend(receiver.receive(2));
+            received.sort();
+
+            assert_eq!(received, vec![(1, vec![1; 4]), (2, vec![2; 4])]);
+        });
+    }
+
+    #[test]
+    fn producer_reuses_records_zeroed_by_receiver() {
+        loom::model(|| {
+            // Two records fill the buffer, so the third one only fits once the first is consumed.
+            let (mut sender, mut receiver) = RingBuffer::new(32).unwrap().split();
+            sender.send(1, &[1; 4]).unwrap();
+            sender.send(2, &[2; 4]).unwrap();
+
+            let producer = thread::spawn(move || {
+                while sender.send(3, &[3; 4]).is_err() {
+                    thread::yield_now();
+                }
+            });
+
+            let mut received = Vec::new();
+            while received.len() < 3 {
+                received.extend(receiver.receive(1));
+                thread::yield_now();
+            }
+            producer.join().unwrap();
+
+            assert_eq!(
+                received,
+                vec![(1, vec![1; 4]), (2, vec![2; 4]), (3, vec![3; 4])]
+            );
+        });
+    }
+
+    #[test]
+    fn one_to_one_producer_pads_and_wraps() {
+        loom::model(|| {
+            // The third record doesn't fit before the end of the buffer, so it pads the end and
+            // wraps to the start once the receiver consumed the second one.
+            let (mut sender, mut receiver) = OneToOneRingBuffer::new(32).unwrap().split();
+            sender.send(1, &[]).unwrap();
+            assert_eq!(receiver.receive(1), vec![(1, vec![])]);
+            sender.send(2, &[2; 4]).unwrap();
+
+            let producer = thread::spawn(move || {
+                while sender.send(3, &[3; 4]).is_err() {
+                    thread::yield_now();
+                }
+            });
+
+            let mut received = Vec::new();
+            while received.len() < 2 {
+                received.extend(receiver.receive(1));
+                thread::yield_now();
+            }
+            producer.join().unwrap();
+
+            assert_eq!(received, vec![(2, vec![2; 4]), (3, vec![3; 4])]);
+        });
+    }
 }
@@ -0,0 +1,63 @@
+//! File backed memory, used to share a ring buffer between processes.
+//!
+//! Place the file on a memory backed file system such as `/dev/shm` to avoid writing the ring
+//! buffer out to disk.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+    ptr::NonNull,
+};
+
+use memmap2::MmapMut;
+
+#[derive(Debug)]
+pub(crate) struct MappedFile {
+    ptr: NonNull<u8>,
+    length: usize,
+    _mmap: MmapMut,
+}
+
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Creates (or truncates) the file at `path` and maps `length` zeroed bytes of it.
+    pub fn create(path: impl AsRef<Path>, length: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(length as u64)?;
+
+        Self::map(&file)
+    }
+
+    /// Maps the whole of the existing file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        Self::map(&file)
+    }
+
+    fn map(file: &File) -> io::Result<Self> {
+        let mut mmap = unsafe { MmapMut::map_mut(file)? };
+
+        Ok(Self {
+            ptr: NonNull::new(mmap.as_mut_ptr()).unwrap(),
+            length: mmap.len(),
+            _mmap: mmap,
+        })
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+}
@@ -0,0 +1,483 @@
+//! Single producer, single consumer ring buffer.
+//!
+//! Follows Aeron's `aeron_spsc_rb`: with only one producer the tail is claimed with a plain release
+//! store instead of a compare and swap. The record format and [`RawDescriptor`](crate::descriptor)
+//! trailer are the same as [`RingBuffer`]'s, so both variants attach to each other's memory and the
+//! consumer side is the same [`Receiver`].
+//!
+//! Only [`OneToOneSender::send`] and [`OneToOneSender::try_claim`] take the single producer path.
+//! The rest of the sending API is shared with [`Sender`](crate::sender::Sender) and claims with a
+//! compare and swap, which never contends with a single producer.
+
+use std::{
+    io::{self, IoSlice},
+    path::Path,
+    ptr::NonNull,
+    sync::Arc,
+};
+
+use crate::{
+    aeron_align, aeron_rb_size,
+    backing::Backing,
+    clock::EpochClock,
+    descriptor::SenderDescriptor,
+    error::Error,
+    message_mut,
+    receiver::Receiver,
+    record_header,
+    sender::{self, check_message, wake_consumer, Claim},
+    sync::Ordering,
+    RingBuffer, AERON_RB_ALIGNMENT, AERON_RB_PADDING_MSG_TYPE_ID, AERON_RB_RECORD_HEADER_LENGTH,
+};
+
+#[derive(Debug)]
+pub struct OneToOneRingBuffer(RingBuffer);
+
+impl OneToOneRingBuffer {
+    pub fn new(capacity: usize) -> Result<Self, Error> {
+        RingBuffer::new(capacity).map(Self)
+    }
+
+    /// # Safety
+    ///
+    /// See [`RingBuffer::from_memory`].
+    pub unsafe fn from_memory(buffer: *mut u8, length: usize) -> Result<Self, Error> {
+        RingBuffer::from_memory(buffer, length).map(Self)
+    }
+
+    /// See [`RingBuffer::from_backing`].
+    ///
+    /// # Safety
+    ///
+    /// See [`RingBuffer::from_backing`].
+    pub unsafe fn from_backing(backing: Backing) -> Result<Self, Error> {
+        unsafe { RingBuffer::from_backing(backing) }.map(Self)
+    }
+
+    /// Creates a ring buffer with `capacity` in a new file at `path`, replacing any existing file.
+    pub fn create_file(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        RingBuffer::create_file(path, capacity).map(Self)
+    }
+
+    /// Attaches to a ring buffer created by [`OneToOneRingBuffer::create_file`] or
+    /// [`RingBuffer::create_file`].
+    ///
+    /// # Safety
+    ///
+    /// See [`RingBuffer::open_file`].
+    pub unsafe fn open_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        unsafe { RingBuffer::open_file(path) }.map(Self)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    pub fn max_message_length(&self) -> usize {
+        self.0.max_message_length()
+    }
+
+    /// See [`RingBuffer::producer_position`].
+    pub fn producer_position(&self) -> i64 {
+        self.0.producer_position()
+    }
+
+    /// See [`RingBuffer::consumer_position`].
+    pub fn consumer_position(&self) -> i64 {
+        self.0.consumer_position()
+    }
+
+    /// See [`RingBuffer::size`].
+    pub fn size(&self) -> usize {
+        self.0.size()
+    }
+
+    /// See [`RingBuffer::next_correlation_id`].
+    pub fn next_correlation_id(&self) -> i64 {
+        self.0.next_correlation_id()
+    }
+
+    /// See [`RingBuffer::consumer_heartbeat_time`].
+    pub fn consumer_heartbeat_time(&self) -> i64 {
+        self.0.consumer_heartbeat_time()
+    }
+
+    pub fn split(self) -> (OneToOneSender, Receiver) {
+        (self.sender(), self.0.receiver())
+    }
+
+    fn sender(&self) -> OneToOneSender {
+        let ring_buffer = &self.0;
+
+        OneToOneSender {
+            buffer: ring_buffer.buffer,
+            capacity: ring_buffer.capacity,
+            descriptor: SenderDescriptor::new(ring_buffer.descriptor, ring_buffer.backing.wakers()),
+            max_message_length: ring_buffer.max_message_length,
+            backing: ring_buffer.backing.clone(),
+        }
+    }
+}
+
+/// The only producer of a [`OneToOneRingBuffer`], hence not `Clone`.
+pub struct OneToOneSender {
+    buffer: NonNull<u8>,
+    capacity: usize,
+    descriptor: SenderDescriptor,
+    max_message_length: usize,
+    backing: Arc<Backing>,
+}
+
+unsafe impl Send for OneToOneSender {}
+
+impl OneToOneSender {
+    /// Attaches the sender to a ring buffer in memory it does not own, for instance shared with
+    /// another process.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must point to `length` bytes holding a ring buffer that stay valid for as long as
+    /// the sender is alive. No other sender may be attached to the ring buffer.
+    pub unsafe fn new(buffer: *mut u8, length: usize) -> Result<Self, Error> {
+        Ok(OneToOneRingBuffer::from_memory(buffer, length)?.sender())
+    }
+
+    /// Attaches the sender to a ring buffer created by [`OneToOneRingBuffer::create_file`] or
+    /// [`RingBuffer::create_file`].
+    ///
+    /// # Safety
+    ///
+    /// See [`RingBuffer::open_file`]. No other sender may be attached to the ring buffer.
+    pub unsafe fn open_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(unsafe { OneToOneRingBuffer::open_file(path) }?.sender())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
+
+    /// Position up to which the sender has claimed space, see [`RingBuffer::size`].
+    pub fn producer_position(&self) -> i64 {
+        self.descriptor.tail_position.load_atomic(Ordering::Acquire)
+    }
+
+    /// Position up to which the receiver has consumed.
+    pub fn consumer_position(&self) -> i64 {
+        self.descriptor.head_position.load_atomic(Ordering::Acquire)
+    }
+
+    /// Number of bytes currently claimed in the ring buffer, see [`RingBuffer::size`].
+    pub fn size(&self) -> usize {
+        aeron_rb_size(
+            self.capacity,
+            || self.descriptor.head_position.load_atomic(Ordering::Acquire),
+            || self.descriptor.tail_position.load_atomic(Ordering::Acquire),
+        )
+    }
+
+    /// Returns a correlation id that is unique among all users of the ring buffer, including other
+    /// processes attached to the same memory.
+    pub fn next_correlation_id(&self) -> i64 {
+        self.descriptor
+            .correlation_counter
+            .fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Last time, in milliseconds since the epoch, the receiver reported it was alive through
+    /// [`Receiver::update_heartbeat`].
+    pub fn consumer_heartbeat_time(&self) -> i64 {
+        self.descriptor
+            .consumer_heartbeat
+            .load_atomic(Ordering::Acquire)
+    }
+
+    /// Returns `true` if the receiver has reported a heartbeat within the last `timeout_ms`
+    /// milliseconds according to `clock`.
+    pub fn is_consumer_alive(&self, clock: &impl EpochClock, timeout_ms: i64) -> bool {
+        let heartbeat = self.consumer_heartbeat_time();
+
+        heartbeat != 0 && clock.time() <= heartbeat + timeout_ms
+    }
+
+    pub fn send(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), Error> {
+        check_message(self.max_message_length, msg_type_id, msg.len())?;
+
+        let record_length: usize = msg.len() + AERON_RB_RECORD_HEADER_LENGTH;
+        let (record_index, tail) = self.claim_capacity(record_length)?;
+
+        unsafe { message_mut(self.buffer, record_index, msg.len()) }.copy_from_slice(msg);
+
+        let header = unsafe { record_header(self.buffer, record_index) };
+        header.msg_type_id.store(msg_type_id, Ordering::Relaxed);
+        // Publishes the message, the receiver acquires the length before reading the message.
+        header.length.store(record_length as i32, Ordering::Release);
+        self.descriptor
+            .tail_position
+            .store_atomic(tail, Ordering::Release);
+        wake_consumer(&self.descriptor);
+
+        Ok(())
+    }
+
+    /// See [`Sender::send_vectored`](crate::sender::Sender::send_vectored).
+    pub fn send_vectored(&mut self, msg_type_id: i32, parts: &[IoSlice<'_>]) -> Result<(), Error> {
+        unsafe {
+            sender::send_vectored(
+                self.buffer,
+                self.capacity,
+                self.max_message_length,
+                &self.descriptor,
+                msg_type_id,
+                parts,
+            )
+        }
+    }
+
+    /// See [`Sender::send_batch`](crate::sender::Sender::send_batch).
+    pub fn send_batch(&mut self, messages: &[(i32, &[u8])]) -> Result<usize, Error> {
+        unsafe {
+            sender::send_batch(
+                self.buffer,
+                self.capacity,
+                self.max_message_length,
+                &self.descriptor,
+                messages,
+            )
+        }
+    }
+
+    /// See [`Sender::send_async`](crate::sender::Sender::send_async).
+    #[cfg(all(feature = "async", not(loom)))]
+    pub async fn send_async(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), Error> {
+        std::future::poll_fn(|cx| unsafe {
+            sender::poll_send(
+                cx,
+                self.buffer,
+                self.capacity,
+                self.max_message_length,
+                &self.descriptor,
+                msg_type_id,
+                msg,
+            )
+        })
+        .await
+    }
+
+    /// Claims space for a message of `length` bytes, see
+    /// [`Sender::try_claim`](crate::sender::Sender::try_claim).
+    pub fn try_claim(&mut self, msg_type_id: i32, length: usize) -> Result<Claim<'_>, Error> {
+        check_message(self.max_message_length, msg_type_id, length)?;
+
+        let record_length: usize = length + AERON_RB_RECORD_HEADER_LENGTH;
+        let (record_index, tail) = self.claim_capacity(record_length)?;
+
+        let header = unsafe { record_header(self.buffer, record_index) };
+        header.msg_type_id.store(msg_type_id, Ordering::Relaxed);
+        header
+            .length
+            .store(-(record_length as i32), Ordering::Release);
+        self.descriptor
+            .tail_position
+            .store_atomic(tail, Ordering::Release);
+
+        let buffer: &mut [u8] = unsafe { message_mut(self.buffer, record_index, length) };
+
+        Ok(Claim::new(header, buffer, &self.descriptor))
+    }
+
+    /// Finds room for the record, writing padding up to the end of the buffer when it doesn't fit
+    /// there. Returns the record index and the tail to store once the record is written.
+    fn claim_capacity(&mut self, record_length: usize) -> Result<(usize, i64), Error> {
+        let required_capacity: usize = aeron_align(record_length, AERON_RB_ALIGNMENT);
+        let mask: usize = self.capacity - 1;
+
+        // Only this sender stores the tail and the head cache, and every head it cached was
+        // acquired from the receiver first.
+        let tail: i64 = self.descriptor.tail_position.load_atomic(Ordering::Relaxed);
+        let mut head: i64 = self
+            .descriptor
+            .head_cache_position
+            .load_atomic(Ordering::Relaxed);
+
+        if required_capacity as i64 > self.capacity as i64 - (tail - head) {
+            head = self.descriptor.head_position.load_atomic(Ordering::Acquire);
+
+            if required_capacity as i64 > self.capacity as i64 - (tail - head) {
+                return Err(Error::InsufficientCapacity);
+            }
+
+            self.descriptor
+                .head_cache_position
+                .store_atomic(head, Ordering::Relaxed);
+        }
+
+        let mut padding: usize = 0;
+        let mut record_index: usize = tail as usize & mask;
+        let to_buffer_end_length: usize = self.capacity - record_index;
+
+        if required_capacity > to_buffer_end_length {
+            let mut head_index = head as usize & mask;
+
+            if required_capacity > head_index {
+                head = self.descriptor.head_position.load_atomic(Ordering::Acquire);
+                head_index = head as usize & mask;
+
+                if required_capacity > head_index {
+                    return Err(Error::InsufficientCapacity);
+                }
+
+                self.descriptor
+                    .head_cache_position
+                    .store_atomic(head, Ordering::Relaxed);
+            }
+
+            padding = to_buffer_end_length;
+        }
+
+        if padding != 0 {
+            let header = unsafe { record_header(self.buffer, record_index) };
+
+            header
+                .msg_type_id
+                .store(AERON_RB_PADDING_MSG_TYPE_ID, Ordering::Relaxed);
+            header.length.store(padding as i32, Ordering::Release);
+            record_index = 0;
+        }
+
+        Ok((
+            record_index,
+            tail + required_capacity as i64 + padding as i64,
+        ))
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::io::IoSlice;
+
+    use super::{OneToOneRingBuffer, OneToOneSender};
+    use crate::{error::Error, receiver::Receiver, sender::Sender, RingBuffer};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("agrona-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn send_and_claim_are_received_in_order() {
+        let (mut sender, mut receiver) = OneToOneRingBuffer::new(1024).unwrap().split();
+
+        sender.send(1, &[1, 2, 3]).unwrap();
+        let mut claim = sender.try_claim(2, 2).unwrap();
+        claim.copy_from_slice(&[4, 5]);
+        assert_eq!(receiver.receive(10), [(1, vec![1, 2, 3])]);
+        claim.commit();
+        sender.try_claim(3, 4).unwrap().abort();
+        sender.send(4, &[6]).unwrap();
+
+        assert_eq!(receiver.receive(10), [(2, vec![4, 5]), (4, vec![6])]);
+        assert_eq!(sender.size(), 0);
+    }
+
+    #[test]
+    fn shared_sends_mix_with_single_producer_sends() {
+        let (mut sender, mut receiver) = OneToOneRingBuffer::new(64).unwrap().split();
+
+        for round in 0..10u8 {
+            sender.send(1, &[round; 3]).unwrap();
+            sender
+                .send_vectored(2, &[IoSlice::new(&[round; 2]), IoSlice::new(&[round])])
+                .unwrap();
+            assert_eq!(
+                sender.send_batch(&[(3, &[round; 1]), (4, &[round; 2])]),
+                Ok(2)
+            );
+
+            assert_eq!(
+                receiver.receive(10),
+                [
+                    (1, vec![round; 3]),
+                    (2, vec![round; 3]),
+                    (3, vec![round; 1]),
+                    (4, vec![round; 2])
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn full_buffer_is_reported() {
+        let (mut sender, mut receiver) = OneToOneRingBuffer::new(64).unwrap().split();
+
+        for _ in 0..4 {
+            sender.send(1, &[0; 8]).unwrap();
+        }
+        assert_eq!(sender.send(1, &[0; 8]), Err(Error::InsufficientCapacity));
+
+        assert_eq!(receiver.receive(1).len(), 1);
+        assert_eq!(sender.send(1, &[0; 8]), Ok(()));
+    }
+
+    #[test]
+    fn variants_attach_to_each_others_files() {
+        let path = temp_path("one-to-one");
+
+        let ring_buffer = RingBuffer::create_file(&path, 1024).unwrap();
+        let mut sender = unsafe { OneToOneSender::open_file(&path) }.unwrap();
+        let mut receiver = unsafe { Receiver::open_file(&path) }.unwrap();
+        sender.send(1, &[1]).unwrap();
+        assert_eq!(receiver.receive(10), [(1, vec![1])]);
+        drop((ring_buffer, sender, receiver));
+
+        let ring_buffer = OneToOneRingBuffer::create_file(&path, 1024).unwrap();
+        let mut sender = unsafe { Sender::open_file(&path) }.unwrap();
+        let mut receiver = unsafe { Receiver::open_file(&path) }.unwrap();
+        sender.send(2, &[2]).unwrap();
+        assert_eq!(receiver.receive(10), [(2, vec![2])]);
+        assert_eq!(ring_buffer.consumer_position(), 16);
+        drop((ring_buffer, sender, receiver));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn single_producer_stress() {
+        let (mut sender, mut receiver) = OneToOneRingBuffer::new(1024).unwrap().split();
+        let messages: u64 = 100_000;
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for sequence in 0..messages {
+                    // Varying lengths so the records wrap at different offsets.
+                    let msg = sequence.to_le_bytes();
+                    let length = 1 + sequence as usize % msg.len();
+
+                    while sender.send(1, &msg[..length]).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+
+            let mut next_sequence: u64 = 0;
+            while next_sequence < messages {
+                let count = receiver.read(
+                    |_, data| {
+                        let length = 1 + next_sequence as usize % 8;
+                        assert_eq!(data, &next_sequence.to_le_bytes()[..length]);
+                        next_sequence += 1;
+                    },
+                    usize::MAX,
+                );
+
+                if count == 0 {
+                    std::thread::yield_now();
+                }
+            }
+        });
+
+        assert!(receiver.receive(usize::MAX).is_empty());
+    }
+}
@@ -1,107 +1,664 @@
-use std::{
-    ptr::NonNull,
-    slice,
-    sync::{
-        atomic::{compiler_fence, AtomicUsize, Ordering},
-        Arc,
-    },
-};
+use std::{io, marker::PhantomData, mem, path::Path, ptr::NonNull, sync::Arc};
 
 use crate::{
-    aeron_align, aeron_rb_message_offset, descriptor::ReceiverDescriptor, free_buffer,
-    RecordDescriptor, AERON_RB_ALIGNMENT, AERON_RB_PADDING_MSG_TYPE_ID,
+    aeron_align, aeron_rb_size, backing::Backing, descriptor::ReceiverDescriptor, error::Error,
+    fragment::FragmentAssembler, message, record_header, sync::Ordering, zero_records,
+    RecordDescriptor, RingBuffer, AERON_RB_ALIGNMENT, AERON_RB_PADDING_MSG_TYPE_ID,
     AERON_RB_RECORD_HEADER_LENGTH,
 };
+#[cfg(all(feature = "blocking", not(loom)))]
+use {
+    crate::futex,
+    std::{
+        sync::atomic::fence,
+        time::{Duration, Instant},
+    },
+};
+#[cfg(all(feature = "async", not(loom)))]
+use {
+    bytes::Bytes,
+    futures_core::Stream,
+    std::{
+        pin::Pin,
+        task::{Context, Poll},
+    },
+};
 
-// IDEA: capacity could be a const generic (<const N: usize>)
 pub struct Receiver {
     pub(crate) buffer: NonNull<u8>,
     pub(crate) capacity: usize,
     pub(crate) descriptor: ReceiverDescriptor,
-    pub(crate) _max_message_length: usize,
-    pub(crate) reference_count: Arc<AtomicUsize>,
+    pub(crate) max_message_length: usize,
+    pub(crate) backing: Arc<Backing>,
 }
 
 unsafe impl Send for Receiver {}
 
+/// Action returned by the handler of [`Receiver::controlled_read`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlledReadAction {
+    /// Leave the current message unconsumed and stop reading, it is handed out again on the next read.
+    Abort,
+    /// Consume the current message and stop reading.
+    Break,
+    /// Consume the current message and move the head past everything read so far.
+    Commit,
+    /// Consume the current message and keep reading.
+    Continue,
+}
+
 impl Receiver {
-    // simplified version of read without handler.
-    // TODO: implement full version
-    // Idea: return reference to bytes and only increment once dropped
+    /// Attaches a receiver to a ring buffer in memory it does not own, for instance shared with
+    /// another process.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must point to `length` bytes holding a ring buffer that stay valid for as long as
+    /// the receiver is alive.
+    pub unsafe fn new(buffer: *mut u8, length: usize) -> Result<Self, Error> {
+        Ok(RingBuffer::from_memory(buffer, length)?.receiver())
+    }
+
+    /// Attaches a receiver to a ring buffer created by [`RingBuffer::create_file`].
+    ///
+    /// # Safety
+    ///
+    /// See [`RingBuffer::open_file`].
+    pub unsafe fn open_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(unsafe { RingBuffer::open_file(path) }?.receiver())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
+
+    /// Position up to which producers have claimed space, see [`RingBuffer::size`].
+    pub fn producer_position(&self) -> i64 {
+        self.descriptor.tail_position.read_atomic(Ordering::Acquire)
+    }
+
+    /// Position up to which the receiver has consumed.
+    pub fn consumer_position(&self) -> i64 {
+        self.descriptor.head_position.load_atomic(Ordering::Acquire)
+    }
+
+    /// Number of bytes currently claimed in the ring buffer, see [`RingBuffer::size`].
+    pub fn size(&self) -> usize {
+        aeron_rb_size(
+            self.capacity,
+            || self.descriptor.head_position.load_atomic(Ordering::Acquire),
+            || self.descriptor.tail_position.read_atomic(Ordering::Acquire),
+        )
+    }
+
+    /// Returns a correlation id that is unique among all users of the ring buffer, including other
+    /// processes attached to the same memory.
+    pub fn next_correlation_id(&self) -> i64 {
+        self.descriptor
+            .correlation_counter
+            .fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Reports the receiver as alive at `now_ms`, milliseconds since the epoch, to the producers.
+    pub fn update_heartbeat(&self, now_ms: i64) {
+        self.descriptor
+            .consumer_heartbeat
+            .store_atomic(now_ms, Ordering::Release);
+    }
+
+    /// Reads up to `message_count_limit` messages and copies them out of the ring buffer.
     pub fn receive(&mut self, message_count_limit: usize) -> Vec<(i32, Vec<u8>)> {
+        let mut read_buffer: Vec<(i32, Vec<u8>)> = Vec::new();
+
+        self.read(
+            |msg_type_id, data| read_buffer.push((msg_type_id, data.to_vec())),
+            message_count_limit,
+        );
+
+        read_buffer
+    }
+
+    /// Like [`Receiver::receive`], but parks the thread while the ring buffer is empty, until a
+    /// message arrives or `timeout` elapses.
+    ///
+    /// Senders only wake the receiver when built with the `blocking` feature, in every process
+    /// attached to the ring buffer.
+    #[cfg(all(feature = "blocking", not(loom)))]
+    pub fn receive_blocking(
+        &mut self,
+        message_count_limit: usize,
+        timeout: Duration,
+    ) -> Vec<(i32, Vec<u8>)> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let received = self.receive(message_count_limit);
+            let now = Instant::now();
+
+            if !received.is_empty() || now >= deadline {
+                return received;
+            }
+
+            self.park(deadline - now);
+        }
+    }
+
+    /// Announces the receiver is parked and sleeps, unless a record arrived in the meantime.
+    #[cfg(all(feature = "blocking", not(loom)))]
+    fn park(&self, timeout: Duration) {
+        let parked = &self.descriptor.consumer_parked;
+
+        parked.store_atomic(futex::PARKED, Ordering::Relaxed);
+        // Pairs with the fence of a sender between publishing a record and checking the word.
+        fence(Ordering::SeqCst);
+
         let head: i64 = self.descriptor.head_position.load_atomic(Ordering::Relaxed);
         let head_index: usize = head as usize & (self.capacity - 1);
-        let contiguous_block_length: usize = self.capacity - head_index;
-        let mut messages_read: usize = 0;
-        let mut bytes_read: usize = 0;
+        let record_length: i32 = unsafe { record_header(self.buffer, head_index) }
+            .length
+            .load(Ordering::Acquire);
 
-        // TODO: replace buffer by message handler
-        let mut read_buffer: Vec<(i32, Vec<u8>)> = Vec::new();
+        if record_length <= 0 {
+            futex::wait(parked.as_atomic(), futex::PARKED, timeout);
+        }
 
-        while bytes_read < contiguous_block_length && messages_read < message_count_limit {
-            let record_index: usize = head_index + bytes_read;
-            let header: &RecordDescriptor = {
-                let ptr: *mut u8 = unsafe { self.buffer.as_ptr().byte_add(record_index) };
-                unsafe { &*(ptr as *const RecordDescriptor) }
-            };
+        parked.store_atomic(0, Ordering::Relaxed);
+    }
 
-            let record_length: i32;
-            {
-                // aeron_get_volatile_i32(&mut record_length, &header.length);
-                record_length = header.length.load(Ordering::Relaxed);
-                compiler_fence(Ordering::SeqCst);
-            }
+    /// Reads up to `message_count_limit` messages, handing each one to `handler` straight from
+    /// the buffer.
+    ///
+    /// Returns the number of messages read.
+    pub fn read<F>(&mut self, mut handler: F, message_count_limit: usize) -> usize
+    where
+        F: FnMut(i32, &[u8]),
+    {
+        self.controlled_read(
+            |msg_type_id, data| {
+                handler(msg_type_id, data);
+                ControlledReadAction::Continue
+            },
+            message_count_limit,
+        )
+    }
+
+    /// Borrows up to `message_count_limit` messages straight from the buffer.
+    ///
+    /// The messages are consumed, and the head moved past them, once the returned [`Batch`] is
+    /// committed or dropped. Like [`Receiver::controlled_read`], the batch continues from the start
+    /// of the buffer when it reaches its end.
+    pub fn poll(&mut self, message_count_limit: usize) -> Batch<'_> {
+        unsafe {
+            poll(
+                self.buffer,
+                self.capacity,
+                &self.descriptor,
+                message_count_limit,
+            )
+        }
+    }
+
+    /// Reads up to `message_count_limit` fragments sent by
+    /// [`FragmentingSender`](crate::fragment::FragmentingSender)s and hands every message they
+    /// complete to `handler`.
+    ///
+    /// Returns the number of fragments read.
+    pub fn read_fragmented<F>(
+        &mut self,
+        assembler: &mut FragmentAssembler,
+        mut handler: F,
+        fragment_count_limit: usize,
+    ) -> usize
+    where
+        F: FnMut(i32, &[u8]),
+    {
+        self.read(
+            |msg_type_id, fragment| assembler.on_fragment(msg_type_id, fragment, &mut handler),
+            fragment_count_limit,
+        )
+    }
+
+    /// Reads up to `message_count_limit` messages, letting `handler` decide per message how the
+    /// read continues (see [`ControlledReadAction`]).
+    ///
+    /// Returns the number of messages consumed.
+    ///
+    /// A read that reaches the end of the buffer consumes what it read so far and continues, once,
+    /// from the start of the buffer.
+    // Idea: return reference to bytes and only increment once dropped
+    pub fn controlled_read<F>(&mut self, handler: F, message_count_limit: usize) -> usize
+    where
+        F: FnMut(i32, &[u8]) -> ControlledReadAction,
+    {
+        unsafe {
+            controlled_read(
+                self.buffer,
+                self.capacity,
+                &self.descriptor,
+                handler,
+                message_count_limit,
+            )
+        }
+    }
+
+    /// Unblocks the ring buffer when a producer died between claiming space and publishing its
+    /// record, which would otherwise stop the receiver at that record forever.
+    ///
+    /// The stuck region, from the head up to the next record, is turned into padding. Only call
+    /// this once the producer is known to be gone, a slow producer is indistinguishable from a
+    /// dead one. Returns `true` if the ring buffer was unblocked.
+    pub fn unblock(&mut self) -> bool {
+        unsafe { unblock(self.buffer, self.capacity, &self.descriptor) }
+    }
+}
+
+/// Yields messages one at a time, waiting for the next one while the ring buffer is empty. The
+/// stream never ends.
+///
+/// The receiver task is only woken by senders split from the same ring buffer. Messages from other
+/// processes, or from senders opened separately, are picked up on the next poll only.
+#[cfg(all(feature = "async", not(loom)))]
+impl Stream for Receiver {
+    type Item = (i32, Bytes);
 
-            if record_length <= 0 {
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        let mut message = None;
+        let mut read = |msg_type_id: i32, msg: &[u8]| {
+            message = Some((msg_type_id, Bytes::copy_from_slice(msg)));
+        };
+
+        if this.read(&mut read, 1) == 0 {
+            this.descriptor.wakers().register_receiver(cx.waker());
+            // A sender may have published just before the waker was registered.
+            this.read(&mut read, 1);
+        }
+
+        match message {
+            Some(message) => Poll::Ready(Some(message)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Reads records for [`Receiver`] and [`fixed::Receiver`](crate::fixed::Receiver), see
+/// [`Receiver::controlled_read`].
+///
+/// # Safety
+///
+/// `buffer` must hold a ring buffer of `capacity` bytes described by `descriptor`.
+#[inline]
+pub(crate) unsafe fn controlled_read<F>(
+    buffer: NonNull<u8>,
+    capacity: usize,
+    descriptor: &ReceiverDescriptor,
+    mut handler: F,
+    message_count_limit: usize,
+) -> usize
+where
+    F: FnMut(i32, &[u8]) -> ControlledReadAction,
+{
+    // Only the receiver stores the head.
+    let mut head: i64 = descriptor.head_position.load_atomic(Ordering::Relaxed);
+    let mut head_index: usize = head as usize & (capacity - 1);
+    let mut messages_read: usize = 0;
+    let mut bytes_read: usize = 0;
+    let mut wrapped: bool = false;
+
+    while messages_read < message_count_limit {
+        if head_index + bytes_read == capacity {
+            // Read up to the end of the buffer, continue once from its start.
+            if wrapped {
                 break;
             }
 
-            bytes_read += aeron_align(record_length as usize, AERON_RB_ALIGNMENT);
-            let msg_type_id: i32 = header.msg_type_id.load(Ordering::Relaxed);
+            if bytes_read != 0 {
+                release(buffer, descriptor, head, head_index, bytes_read);
+                head += bytes_read as i64;
+            }
+            head_index = 0;
+            bytes_read = 0;
+            wrapped = true;
+        }
+
+        let record_index: usize = head_index + bytes_read;
+        let header: &RecordDescriptor = unsafe { record_header(buffer, record_index) };
+
+        // Pairs with the release store publishing the record.
+        let record_length: i32 = header.length.load(Ordering::Acquire);
+
+        if record_length <= 0 {
+            break;
+        }
+
+        let aligned_length = aeron_align(record_length as usize, AERON_RB_ALIGNMENT);
+        bytes_read += aligned_length;
+        let msg_type_id: i32 = header.msg_type_id.load(Ordering::Relaxed);
+
+        if msg_type_id == AERON_RB_PADDING_MSG_TYPE_ID {
+            continue;
+        }
+
+        let data: &[u8] = unsafe {
+            message(
+                buffer,
+                record_index,
+                record_length as usize - AERON_RB_RECORD_HEADER_LENGTH,
+            )
+        };
 
-            if msg_type_id == AERON_RB_PADDING_MSG_TYPE_ID {
-                continue;
+        let action = handler(msg_type_id, data);
+
+        if action == ControlledReadAction::Abort {
+            bytes_read -= aligned_length;
+            break;
+        }
+
+        messages_read += 1;
+
+        match action {
+            ControlledReadAction::Break => break,
+            ControlledReadAction::Commit => {
+                release(buffer, descriptor, head, head_index, bytes_read);
+                head += bytes_read as i64;
+                head_index += bytes_read;
+                bytes_read = 0;
             }
+            _ => {}
+        }
+    }
 
-            messages_read += 1;
-            // TODO: Return special type that increments head once dropped
-            let data: &[u8] = {
-                let index = aeron_rb_message_offset(record_index);
-                let ptr = unsafe { self.buffer.as_ptr().byte_add(index) };
-                unsafe {
-                    slice::from_raw_parts(
-                        ptr,
-                        record_length as usize - AERON_RB_RECORD_HEADER_LENGTH,
-                    )
-                }
-            };
-            read_buffer.push((msg_type_id, data.to_vec()));
+    if bytes_read != 0 {
+        release(buffer, descriptor, head, head_index, bytes_read);
+    }
+
+    messages_read
+}
+
+/// Finds the messages for a [`Batch`], see [`Receiver::poll`].
+///
+/// # Safety
+///
+/// `buffer` must hold a ring buffer of `capacity` bytes described by `descriptor`.
+#[inline]
+pub(crate) unsafe fn poll(
+    buffer: NonNull<u8>,
+    capacity: usize,
+    descriptor: &ReceiverDescriptor,
+    message_count_limit: usize,
+) -> Batch<'_> {
+    // Only the receiver stores the head.
+    let head: i64 = descriptor.head_position.load_atomic(Ordering::Relaxed);
+    let head_index: usize = head as usize & (capacity - 1);
+    let mut messages: usize = 0;
+    let mut bytes: usize = 0;
+    // Records wrapped around to the start of the buffer, they can't reach the head index.
+    let mut wrapped_bytes: usize = 0;
+
+    while messages < message_count_limit {
+        let record_index: usize = if head_index + bytes < capacity {
+            head_index + bytes
+        } else if wrapped_bytes < head_index {
+            wrapped_bytes
+        } else {
+            break;
+        };
+        let header: &RecordDescriptor = unsafe { record_header(buffer, record_index) };
+
+        // Pairs with the release store publishing the record.
+        let record_length: i32 = header.length.load(Ordering::Acquire);
+
+        if record_length <= 0 {
+            break;
+        }
+
+        let aligned_length = aeron_align(record_length as usize, AERON_RB_ALIGNMENT);
+        if head_index + bytes < capacity {
+            bytes += aligned_length;
+        } else {
+            wrapped_bytes += aligned_length;
+        }
+
+        if header.msg_type_id.load(Ordering::Relaxed) != AERON_RB_PADDING_MSG_TYPE_ID {
+            messages += 1;
         }
+    }
+
+    Batch {
+        buffer,
+        descriptor,
+        head,
+        head_index,
+        bytes,
+        wrapped_bytes,
+        messages,
+    }
+}
+
+/// See [`Receiver::unblock`].
+///
+/// # Safety
+///
+/// `buffer` must hold a ring buffer of `capacity` bytes described by `descriptor`.
+#[inline]
+pub(crate) unsafe fn unblock(
+    buffer: NonNull<u8>,
+    capacity: usize,
+    descriptor: &ReceiverDescriptor,
+) -> bool {
+    let head: i64 = descriptor.head_position.load_atomic(Ordering::Acquire);
+    let tail: i64 = descriptor.tail_position.read_atomic(Ordering::Acquire);
+
+    if head == tail {
+        return false;
+    }
+
+    let mask: usize = capacity - 1;
+    let consumer_index: usize = head as usize & mask;
+    let producer_index: usize = tail as usize & mask;
+
+    let header: &RecordDescriptor = unsafe { record_header(buffer, consumer_index) };
+    let length: i32 = header.length.load(Ordering::Acquire);
 
-        if bytes_read != 0 {
-            // Set all the bytes read to 0 with memset
-            let destination_ptr: *mut u8 = unsafe { self.buffer.as_ptr().byte_add(head_index) };
-            unsafe { destination_ptr.write_bytes(0, bytes_read) };
-            {
-                // aeron_put_ordered_i64(self.descriptor.head_position(), head + bytes_read as i64);
+    if length < 0 {
+        // The producer claimed the record but never committed it.
+        header
+            .msg_type_id
+            .store(AERON_RB_PADDING_MSG_TYPE_ID, Ordering::Relaxed);
+        header.length.store(-length, Ordering::Release);
+        return true;
+    }
+
+    if length == 0 {
+        // The producer claimed space but died before writing the header, look for the next
+        // record to find out how much.
+        let limit: usize = if producer_index > consumer_index {
+            producer_index
+        } else {
+            capacity
+        };
+
+        let mut index: usize = consumer_index + AERON_RB_ALIGNMENT;
+        while index < limit {
+            let length = unsafe { record_header(buffer, index) }
+                .length
+                .load(Ordering::Acquire);
+
+            if length != 0 {
+                if is_zeroed(buffer, consumer_index, index) {
+                    header
+                        .msg_type_id
+                        .store(AERON_RB_PADDING_MSG_TYPE_ID, Ordering::Relaxed);
+                    header
+                        .length
+                        .store((index - consumer_index) as i32, Ordering::Release);
+                    return true;
+                }
 
-                compiler_fence(Ordering::SeqCst);
-                self.descriptor
-                    .head_position
-                    .store_atomic(head + bytes_read as i64, Ordering::Relaxed);
+                break;
             }
+
+            index += AERON_RB_ALIGNMENT;
         }
+    }
 
-        // return vec
-        read_buffer
+    false
+}
+
+/// Scans back from `index` to `limit` to confirm no producer started a record in between.
+unsafe fn is_zeroed(buffer: NonNull<u8>, limit: usize, index: usize) -> bool {
+    (limit..index)
+        .step_by(AERON_RB_ALIGNMENT)
+        .rev()
+        .all(|index| {
+            unsafe { record_header(buffer, index) }
+                .length
+                .load(Ordering::Acquire)
+                == 0
+        })
+}
+
+/// Zeroes the `bytes_read` bytes consumed from `head_index` and moves the head past them.
+unsafe fn release(
+    buffer: NonNull<u8>,
+    descriptor: &ReceiverDescriptor,
+    head: i64,
+    head_index: usize,
+    bytes_read: usize,
+) {
+    // Set all the bytes read to 0 with memset
+    unsafe { zero_records(buffer, head_index, bytes_read) };
+    // Producers acquire the head before reusing the zeroed records.
+    descriptor
+        .head_position
+        .store_atomic(head + bytes_read as i64, Ordering::Release);
+    descriptor.wakers().wake_senders();
+}
+
+/// Messages borrowed from the ring buffer by [`Receiver::poll`].
+///
+/// The receiver stays borrowed while the batch is alive, the messages are consumed once it is
+/// committed or dropped.
+pub struct Batch<'a> {
+    buffer: NonNull<u8>,
+    descriptor: &'a ReceiverDescriptor,
+    head: i64,
+    head_index: usize,
+    bytes: usize,
+    wrapped_bytes: usize,
+    messages: usize,
+}
+
+impl Batch<'_> {
+    /// Number of messages in the batch.
+    pub fn len(&self) -> usize {
+        self.messages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages == 0
+    }
+
+    /// Iterates over the `(msg_type_id, message)` of the batch in order.
+    pub fn iter(&self) -> BatchIter<'_> {
+        BatchIter {
+            buffer: self.buffer,
+            index: self.head_index,
+            end: self.head_index + self.bytes,
+            wrapped_end: self.wrapped_bytes,
+            batch: PhantomData,
+        }
+    }
+
+    /// Consumes the messages, like dropping the batch does.
+    pub fn commit(self) {
+        drop(self);
     }
 }
 
-impl Drop for Receiver {
+impl Drop for Batch<'_> {
     fn drop(&mut self) {
-        if self.reference_count.fetch_sub(1, Ordering::Relaxed) == 0 {
+        if self.bytes != 0 {
+            unsafe {
+                release(
+                    self.buffer,
+                    self.descriptor,
+                    self.head,
+                    self.head_index,
+                    self.bytes,
+                )
+            };
+        }
+
+        if self.wrapped_bytes != 0 {
             unsafe {
-                free_buffer(self.buffer, self.capacity);
+                release(
+                    self.buffer,
+                    self.descriptor,
+                    self.head + self.bytes as i64,
+                    0,
+                    self.wrapped_bytes,
+                )
+            };
+        }
+    }
+}
+
+impl<'b> IntoIterator for &'b Batch<'_> {
+    type Item = (i32, &'b [u8]);
+    type IntoIter = BatchIter<'b>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the messages of a [`Batch`].
+pub struct BatchIter<'b> {
+    buffer: NonNull<u8>,
+    index: usize,
+    end: usize,
+    wrapped_end: usize,
+    batch: PhantomData<&'b Batch<'b>>,
+}
+
+impl<'b> Iterator for BatchIter<'b> {
+    type Item = (i32, &'b [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.index == self.end {
+                if self.wrapped_end == 0 {
+                    return None;
+                }
+
+                self.index = 0;
+                self.end = mem::take(&mut self.wrapped_end);
+            }
+
+            let record_index: usize = self.index;
+            let header: &RecordDescriptor = unsafe { record_header(self.buffer, record_index) };
+
+            // Already acquired by `poll`, and left alone by producers until the batch is dropped.
+            let record_length: i32 = header.length.load(Ordering::Relaxed);
+            let msg_type_id: i32 = header.msg_type_id.load(Ordering::Relaxed);
+            self.index += aeron_align(record_length as usize, AERON_RB_ALIGNMENT);
+
+            if msg_type_id != AERON_RB_PADDING_MSG_TYPE_ID {
+                let data: &[u8] = unsafe {
+                    message(
+                        self.buffer,
+                        record_index,
+                        record_length as usize - AERON_RB_RECORD_HEADER_LENGTH,
+                    )
+                };
+
+                return Some((msg_type_id, data));
             }
         }
     }
@@ -1,195 +1,578 @@
 use std::{
-    ptr::{self, NonNull},
-    sync::{
-        atomic::{compiler_fence, AtomicUsize, Ordering},
-        Arc,
-    },
+    io::{self, IoSlice},
+    mem,
+    ops::{Deref, DerefMut},
+    path::Path,
+    ptr::NonNull,
+    sync::Arc,
 };
 
 use crate::{
-    aeron_align, aeron_put_ordered_i32, aeron_rb_invalid_msg_type_id, aeron_rb_message_offset,
-    descriptor::SenderDescriptor, free_buffer, RecordDescriptor, AERON_RB_ALIGNMENT,
-    AERON_RB_PADDING_MSG_TYPE_ID, AERON_RB_RECORD_HEADER_LENGTH,
+    aeron_align, aeron_rb_invalid_msg_type_id, aeron_rb_size, backing::Backing, clock::EpochClock,
+    descriptor::SenderDescriptor, error::Error, message_mut, record_header, sync::Ordering,
+    RecordDescriptor, RingBuffer, AERON_RB_ALIGNMENT, AERON_RB_PADDING_MSG_TYPE_ID,
+    AERON_RB_RECORD_HEADER_LENGTH,
 };
+#[cfg(all(feature = "async", not(loom)))]
+use std::{
+    future::poll_fn,
+    task::{Context, Poll},
+};
+#[cfg(all(feature = "blocking", not(loom)))]
+use {crate::futex, std::sync::atomic::fence};
 
-// IDEA: capacity could be a const generic (<const N: usize>)
 pub struct Sender {
     pub(crate) buffer: NonNull<u8>,
     pub(crate) capacity: usize,
     pub(crate) descriptor: SenderDescriptor,
     pub(crate) max_message_length: usize,
-    pub(crate) reference_count: Arc<AtomicUsize>,
+    pub(crate) backing: Arc<Backing>,
 }
 
 unsafe impl Send for Sender {}
 
+/// Every clone is an additional producer on the same ring buffer.
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        Self {
+            buffer: self.buffer,
+            capacity: self.capacity,
+            descriptor: self.descriptor.clone(),
+            max_message_length: self.max_message_length,
+            backing: self.backing.clone(),
+        }
+    }
+}
+
 impl Sender {
-    pub unsafe fn new(buffer: *mut u8, length: usize) -> Result<Self, ()> {
-        todo!()
+    /// Attaches a sender to a ring buffer in memory it does not own, for instance shared with
+    /// another process.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must point to `length` bytes holding a ring buffer that stay valid for as long as
+    /// the sender, or any of its clones, is alive.
+    pub unsafe fn new(buffer: *mut u8, length: usize) -> Result<Self, Error> {
+        Ok(RingBuffer::from_memory(buffer, length)?.sender())
     }
 
-    pub fn send(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), ()> {
-        if msg.len() > self.max_message_length || aeron_rb_invalid_msg_type_id(msg_type_id) {
-            return Err(());
-        }
+    /// Attaches a sender to a ring buffer created by [`RingBuffer::create_file`].
+    ///
+    /// # Safety
+    ///
+    /// See [`RingBuffer::open_file`].
+    pub unsafe fn open_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(unsafe { RingBuffer::open_file(path) }?.sender())
+    }
 
-        let record_length: usize = msg.len() + AERON_RB_RECORD_HEADER_LENGTH;
-        let record_index = self.claim_capacity(record_length)?;
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
 
-        let record_header: *mut RecordDescriptor =
-            unsafe { self.buffer.as_ptr().byte_add(record_index as usize) }
-                as *mut RecordDescriptor;
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
 
-        {
-            // Probably wrong, should likely be an atomic (lets see if loom caches this).
-            // aeron_put_ordered_i32(&unsafe { &*record_header }.length, -(record_length as i32));
-            compiler_fence(Ordering::SeqCst);
-            unsafe { &*record_header }
-                .length
-                .store(-(record_length as i32), Ordering::Relaxed);
-        }
+    /// Position up to which producers have claimed space, see [`RingBuffer::size`].
+    pub fn producer_position(&self) -> i64 {
+        self.descriptor.tail_position.load_atomic(Ordering::Acquire)
+    }
 
-        let index = aeron_rb_message_offset(record_index as usize);
-        let destination_ptr: *mut u8 = unsafe { self.buffer.as_ptr().byte_add(index) };
-        unsafe { ptr::copy_nonoverlapping(msg.as_ptr(), destination_ptr, msg.len()) };
+    /// Position up to which the receiver has consumed.
+    pub fn consumer_position(&self) -> i64 {
+        self.descriptor.head_position.load_atomic(Ordering::Acquire)
+    }
 
-        unsafe { &*record_header }
-            .msg_type_id
-            .store(msg_type_id, Ordering::Relaxed);
-        aeron_put_ordered_i32(&unsafe { &*record_header }.length, record_length as i32);
+    /// Number of bytes currently claimed in the ring buffer, see [`RingBuffer::size`].
+    pub fn size(&self) -> usize {
+        aeron_rb_size(
+            self.capacity,
+            || self.descriptor.head_position.load_atomic(Ordering::Acquire),
+            || self.descriptor.tail_position.load_atomic(Ordering::Acquire),
+        )
+    }
 
-        Ok(())
+    /// Returns a correlation id that is unique among all users of the ring buffer, including other
+    /// processes attached to the same memory.
+    pub fn next_correlation_id(&self) -> i64 {
+        self.descriptor
+            .correlation_counter
+            .fetch_add(1, Ordering::Relaxed)
     }
 
-    // TODO: check if result can be changed to u32
-    fn claim_capacity(&mut self, record_length: usize) -> Result<i32, ()> {
-        let required_capacity: usize = aeron_align(record_length, AERON_RB_ALIGNMENT);
-        let mask: usize = self.capacity - 1;
-        let mut head: i64;
-        let mut tail: i64;
-        let mut tail_index: usize;
-        let mut padding: usize;
+    /// Last time, in milliseconds since the epoch, the receiver reported it was alive through
+    /// [`Receiver::update_heartbeat`](crate::receiver::Receiver::update_heartbeat).
+    pub fn consumer_heartbeat_time(&self) -> i64 {
+        self.descriptor
+            .consumer_heartbeat
+            .load_atomic(Ordering::Acquire)
+    }
 
-        {
-            // aeron_get_volatile_i64(&mut head, self.descriptor.head_cache_position());
+    /// Returns `true` if the receiver has reported a heartbeat within the last `timeout_ms`
+    /// milliseconds according to `clock`.
+    pub fn is_consumer_alive(&self, clock: &impl EpochClock, timeout_ms: i64) -> bool {
+        let heartbeat = self.consumer_heartbeat_time();
 
-            head = self
-                .descriptor
-                .head_cache_position
-                .load_atomic(Ordering::Relaxed);
-            compiler_fence(Ordering::SeqCst);
+        heartbeat != 0 && clock.time() <= heartbeat + timeout_ms
+    }
+
+    pub fn send(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), Error> {
+        self.send_vectored(msg_type_id, &[IoSlice::new(msg)])
+    }
+
+    /// Sends the concatenation of `parts` as one message, without concatenating them first.
+    pub fn send_vectored(&mut self, msg_type_id: i32, parts: &[IoSlice<'_>]) -> Result<(), Error> {
+        unsafe {
+            send_vectored(
+                self.buffer,
+                self.capacity,
+                self.max_message_length,
+                &self.descriptor,
+                msg_type_id,
+                parts,
+            )
+        }
+    }
+
+    /// Sends all `messages` as consecutive records claimed at once, which takes a single compare
+    /// and swap on the tail instead of one per message.
+    ///
+    /// Either all messages are sent or none. The receiver sees them in order. Returns the number of
+    /// messages sent.
+    ///
+    /// The records must take at most half the capacity, see [`Error::BatchTooLong`].
+    pub fn send_batch(&mut self, messages: &[(i32, &[u8])]) -> Result<usize, Error> {
+        unsafe {
+            send_batch(
+                self.buffer,
+                self.capacity,
+                self.max_message_length,
+                &self.descriptor,
+                messages,
+            )
         }
+    }
 
-        loop {
-            {
-                // aeron_get_volatile_i64(&mut tail, self.descriptor.tail_position());
-                tail = self.descriptor.tail_position.load_atomic(Ordering::Relaxed);
-                compiler_fence(Ordering::SeqCst);
+    /// Claims space for a message of `length` bytes and hands out a [`Claim`] to write it in place.
+    ///
+    /// The record is only published to the receiver once [`Claim::commit`] is called. Dropping the
+    /// claim without committing it aborts it, turning the record into padding.
+    pub fn try_claim(&mut self, msg_type_id: i32, length: usize) -> Result<Claim<'_>, Error> {
+        unsafe {
+            try_claim(
+                self.buffer,
+                self.capacity,
+                self.max_message_length,
+                &self.descriptor,
+                msg_type_id,
+                length,
+            )
+        }
+    }
+
+    /// Like [`Sender::send`], but waits for the receiver to free space instead of failing with
+    /// [`Error::InsufficientCapacity`].
+    ///
+    /// The task is only woken by the receiver split from the same ring buffer, one in another
+    /// process leaves it waiting until it is polled again.
+    #[cfg(all(feature = "async", not(loom)))]
+    pub async fn send_async(&mut self, msg_type_id: i32, msg: &[u8]) -> Result<(), Error> {
+        poll_fn(|cx| unsafe {
+            poll_send(
+                cx,
+                self.buffer,
+                self.capacity,
+                self.max_message_length,
+                &self.descriptor,
+                msg_type_id,
+                msg,
+            )
+        })
+        .await
+    }
+}
+
+/// Sends a record or registers the task to be woken once the receiver frees space, shared by the
+/// `send_async` of every sender.
+///
+/// # Safety
+///
+/// As for [`send_vectored`].
+#[cfg(all(feature = "async", not(loom)))]
+pub(crate) unsafe fn poll_send(
+    cx: &mut Context<'_>,
+    buffer: NonNull<u8>,
+    capacity: usize,
+    max_message_length: usize,
+    descriptor: &SenderDescriptor,
+    msg_type_id: i32,
+    msg: &[u8],
+) -> Poll<Result<(), Error>> {
+    let send = || unsafe {
+        send_vectored(
+            buffer,
+            capacity,
+            max_message_length,
+            descriptor,
+            msg_type_id,
+            &[IoSlice::new(msg)],
+        )
+    };
+
+    match send() {
+        Err(Error::InsufficientCapacity) => {
+            descriptor.wakers().register_sender(cx.waker());
+            // The receiver may have caught up just before the waker was registered.
+            match send() {
+                Err(Error::InsufficientCapacity) => Poll::Pending,
+                result => Poll::Ready(result),
             }
+        }
+        result => Poll::Ready(result),
+    }
+}
+
+/// Writes a record holding `parts` one after the other, shared by [`Sender`] and
+/// [`fixed::Sender`](crate::fixed::Sender).
+///
+/// # Safety
+///
+/// `buffer` must hold a ring buffer of `capacity` bytes described by `descriptor`.
+#[inline]
+pub(crate) unsafe fn send_vectored(
+    buffer: NonNull<u8>,
+    capacity: usize,
+    max_message_length: usize,
+    descriptor: &SenderDescriptor,
+    msg_type_id: i32,
+    parts: &[IoSlice<'_>],
+) -> Result<(), Error> {
+    let length: usize = parts
+        .iter()
+        .try_fold(0usize, |length, part| length.checked_add(part.len()))
+        .ok_or(Error::MessageTooLong {
+            length: usize::MAX,
+            max_message_length,
+        })?;
+    check_message(max_message_length, msg_type_id, length)?;
+
+    let record_length: usize = length + AERON_RB_RECORD_HEADER_LENGTH;
+    let record_index = claim_capacity(buffer, capacity, descriptor, record_length)? as usize;
+
+    let header = unsafe { record_header(buffer, record_index) };
+    header
+        .length
+        .store(-(record_length as i32), Ordering::Release);
+
+    let mut message: &mut [u8] = unsafe { message_mut(buffer, record_index, length) };
+    for part in parts {
+        let (destination, rest) = message.split_at_mut(part.len());
+        destination.copy_from_slice(part);
+        message = rest;
+    }
 
-            let available_capacity = self.capacity - (tail as usize - head as usize);
-            debug_assert!(available_capacity <= self.capacity);
+    header.msg_type_id.store(msg_type_id, Ordering::Relaxed);
+    // Publishes the message, the receiver acquires the length before reading the message.
+    header.length.store(record_length as i32, Ordering::Release);
+    wake_consumer(descriptor);
 
-            if required_capacity > available_capacity {
-                {
-                    // aeron_get_volatile_i64(&mut head, self.descriptor.head_position());
-                    head = self.descriptor.head_position.load_atomic(Ordering::Relaxed);
-                    compiler_fence(Ordering::SeqCst);
-                }
+    Ok(())
+}
 
-                if required_capacity > (self.capacity - (tail as usize - head as usize)) {
-                    return Err(());
-                }
+/// Writes a batch of records claimed at once, shared by [`Sender`] and
+/// [`fixed::Sender`](crate::fixed::Sender).
+///
+/// # Safety
+///
+/// As for [`send_vectored`].
+#[inline]
+pub(crate) unsafe fn send_batch(
+    buffer: NonNull<u8>,
+    capacity: usize,
+    max_message_length: usize,
+    descriptor: &SenderDescriptor,
+    batch: &[(i32, &[u8])],
+) -> Result<usize, Error> {
+    let mut batch_length: usize = 0;
+
+    for &(msg_type_id, msg) in batch {
+        check_message(max_message_length, msg_type_id, msg.len())?;
+        batch_length += aeron_align(
+            msg.len() + AERON_RB_RECORD_HEADER_LENGTH,
+            AERON_RB_ALIGNMENT,
+        );
+    }
 
-                {
-                    // aeron_put_ordered_i64(self.descriptor.head_cache_position(), head);
+    if batch.is_empty() {
+        return Ok(0);
+    }
 
-                    // Probably wrong, should be Release
-                    compiler_fence(std::sync::atomic::Ordering::SeqCst);
-                    self.descriptor
-                        .head_cache_position
-                        .store_atomic(head, Ordering::Relaxed);
-                }
+    // A longer batch that doesn't fit before the end of the buffer needs more room at its start
+    // than the ring buffer can ever free, even once empty.
+    let max_batch_length = capacity / 2;
+    if batch_length > max_batch_length {
+        return Err(Error::BatchTooLong {
+            length: batch_length,
+            max_batch_length,
+        });
+    }
+
+    let batch_index = claim_capacity(buffer, capacity, descriptor, batch_length)? as usize;
+
+    // Marks every record as claimed first, so the receiver can unblock the batch if this producer
+    // dies halfway through.
+    let mut record_index: usize = batch_index;
+    for (_, msg) in batch {
+        let record_length: usize = msg.len() + AERON_RB_RECORD_HEADER_LENGTH;
+        unsafe { record_header(buffer, record_index) }
+            .length
+            .store(-(record_length as i32), Ordering::Release);
+        record_index += aeron_align(record_length, AERON_RB_ALIGNMENT);
+    }
+
+    // The receiver stops at the first record not yet published, so publishing them one by one
+    // keeps them in order.
+    let mut record_index: usize = batch_index;
+    for (msg_type_id, msg) in batch {
+        let record_length: usize = msg.len() + AERON_RB_RECORD_HEADER_LENGTH;
+        let header = unsafe { record_header(buffer, record_index) };
+
+        unsafe { message_mut(buffer, record_index, msg.len()) }.copy_from_slice(msg);
+        header.msg_type_id.store(*msg_type_id, Ordering::Relaxed);
+        header.length.store(record_length as i32, Ordering::Release);
+
+        record_index += aeron_align(record_length, AERON_RB_ALIGNMENT);
+    }
+    wake_consumer(descriptor);
+
+    Ok(batch.len())
+}
+
+/// Claims a record of `length` bytes, shared by [`Sender`] and
+/// [`fixed::Sender`](crate::fixed::Sender).
+///
+/// # Safety
+///
+/// As for [`send_vectored`], and the claim must not outlive the ring buffer.
+#[inline]
+pub(crate) unsafe fn try_claim<'a>(
+    buffer: NonNull<u8>,
+    capacity: usize,
+    max_message_length: usize,
+    descriptor: &'a SenderDescriptor,
+    msg_type_id: i32,
+    length: usize,
+) -> Result<Claim<'a>, Error> {
+    check_message(max_message_length, msg_type_id, length)?;
+
+    let record_length: usize = length + AERON_RB_RECORD_HEADER_LENGTH;
+    let record_index = claim_capacity(buffer, capacity, descriptor, record_length)? as usize;
+
+    let header = unsafe { record_header(buffer, record_index) };
+    header
+        .length
+        .store(-(record_length as i32), Ordering::Release);
+    header.msg_type_id.store(msg_type_id, Ordering::Relaxed);
+
+    let buffer: &mut [u8] = unsafe { message_mut(buffer, record_index, length) };
+
+    Ok(Claim::new(header, buffer, descriptor))
+}
+
+/// # Safety
+///
+/// As for [`send_vectored`].
+#[inline]
+unsafe fn claim_capacity(
+    buffer: NonNull<u8>,
+    capacity: usize,
+    descriptor: &SenderDescriptor,
+    record_length: usize,
+) -> Result<i32, Error> {
+    // TODO: check if result can be changed to u32
+    let required_capacity: usize = aeron_align(record_length, AERON_RB_ALIGNMENT);
+    let mask: usize = capacity - 1;
+    let mut head: i64;
+    let mut tail: i64;
+    let mut tail_index: usize;
+    let mut padding: usize;
+
+    // Every head loaded here, cached or not, was stored by the receiver with release ordering
+    // after zeroing the records it consumed, acquiring it makes the zeroed memory visible.
+    head = descriptor
+        .head_cache_position
+        .load_atomic(Ordering::Acquire);
+
+    loop {
+        tail = descriptor.tail_position.load_atomic(Ordering::Acquire);
+
+        // Signed, with several producers the cached head can lag more than a capacity behind.
+        let available_capacity: i64 = capacity as i64 - (tail - head);
+
+        if required_capacity as i64 > available_capacity {
+            head = descriptor.head_position.load_atomic(Ordering::Acquire);
+
+            if required_capacity as i64 > capacity as i64 - (tail - head) {
+                return Err(Error::InsufficientCapacity);
             }
 
-            padding = 0;
-            tail_index = tail as usize & mask;
-            let to_buffer_end_length = self.capacity - tail_index;
+            descriptor
+                .head_cache_position
+                .store_atomic(head, Ordering::Release);
+        }
+
+        padding = 0;
+        tail_index = tail as usize & mask;
+        let to_buffer_end_length = capacity - tail_index;
 
-            if required_capacity > to_buffer_end_length {
-                // The message doesn't fit between tail index and end of buffer.
+        if required_capacity > to_buffer_end_length {
+            // The message doesn't fit between tail index and end of buffer.
 
-                let mut head_index = head as usize & mask;
+            let mut head_index = head as usize & mask;
+
+            if required_capacity > head_index {
+                // The message doesn't fit between start of buffer and **cached** head index.
+
+                head = descriptor.head_position.load_atomic(Ordering::Acquire);
+                head_index = head as usize & mask;
 
                 if required_capacity > head_index {
-                    // The message doesn't fit between start of buffer and **cached** head index.
-
-                    {
-                        // aeron_get_volatile_i64(&mut head, self.descriptor.head_position());
-
-                        // Should probably be Release - Acquire pair
-                        // equivalent to aeron_get_volatile
-                        head = self.descriptor.head_position.load_atomic(Ordering::Relaxed);
-                        compiler_fence(std::sync::atomic::Ordering::SeqCst);
-                    }
-                    head_index = head as usize & mask;
-
-                    if required_capacity > head_index {
-                        // The message doesn't fit between start of buffer and head index.
-                        return Err(());
-                    }
-
-                    {
-                        // aeron_put_ordered_i64(self.descriptor.head_cache_position(), head);
-
-                        // equivalent to aeron_put_ordered
-                        // Should probably be Release - Acquire pair
-                        compiler_fence(std::sync::atomic::Ordering::SeqCst);
-                        self.descriptor
-                            .head_cache_position
-                            .store_atomic(head, Ordering::Relaxed);
-                    }
+                    // The message doesn't fit between start of buffer and head index.
+                    return Err(Error::InsufficientCapacity);
                 }
 
-                padding = to_buffer_end_length;
+                descriptor
+                    .head_cache_position
+                    .store_atomic(head, Ordering::Release);
             }
 
-            // Exit condition
-            // Probably wrong memory ordering
-            if self.descriptor.tail_position.cas(
-                tail,
-                tail + required_capacity as i64 + padding as i64,
-                Ordering::Relaxed,
-            ) {
-                break;
-            }
+            padding = to_buffer_end_length;
         }
 
-        if padding != 0 {
-            let record_header: &mut RecordDescriptor = {
-                let ptr: *mut u8 = unsafe { self.buffer.as_ptr().byte_add(tail_index) };
-                unsafe { &mut *(ptr as *mut RecordDescriptor) }
-            };
-
-            // TODO: get rid of aeron_put_ordered_i32
-            aeron_put_ordered_i32(&mut record_header.length, -(padding as i32));
-            record_header
-                .msg_type_id
-                .store(AERON_RB_PADDING_MSG_TYPE_ID, Ordering::Relaxed);
-            aeron_put_ordered_i32(&record_header.length, padding as i32);
-            tail_index = 0;
+        // Exit condition
+        if descriptor.tail_position.cas(
+            tail,
+            tail + required_capacity as i64 + padding as i64,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        ) {
+            break;
         }
+    }
 
-        Ok(tail_index as i32)
+    if padding != 0 {
+        let header = unsafe { record_header(buffer, tail_index) };
+
+        header.length.store(-(padding as i32), Ordering::Release);
+        header
+            .msg_type_id
+            .store(AERON_RB_PADDING_MSG_TYPE_ID, Ordering::Relaxed);
+        header.length.store(padding as i32, Ordering::Release);
+        tail_index = 0;
     }
+
+    Ok(tail_index as i32)
 }
 
-impl Drop for Sender {
-    fn drop(&mut self) {
-        if self.reference_count.fetch_sub(1, Ordering::Relaxed) == 0 {
-            unsafe {
-                free_buffer(self.buffer, self.capacity);
-            }
+/// Wakes the receiver if it is parked waiting for a record, see
+/// [`Receiver::receive_blocking`](crate::receiver::Receiver::receive_blocking).
+#[inline]
+pub(crate) fn wake_consumer(descriptor: &SenderDescriptor) {
+    #[cfg(all(feature = "blocking", not(loom)))]
+    {
+        fence(Ordering::SeqCst);
+
+        let parked = &descriptor.consumer_parked;
+        if parked.load_atomic(Ordering::Relaxed) == futex::PARKED
+            && parked.swap_atomic(0, Ordering::Relaxed) == futex::PARKED
+        {
+            futex::wake(parked.as_atomic());
+        }
+    }
+
+    descriptor.wakers().wake_receiver();
+}
+
+pub(crate) fn check_message(
+    max_message_length: usize,
+    msg_type_id: i32,
+    length: usize,
+) -> Result<(), Error> {
+    if length > max_message_length {
+        return Err(Error::MessageTooLong {
+            length,
+            max_message_length,
+        });
+    }
+
+    if aeron_rb_invalid_msg_type_id(msg_type_id) {
+        return Err(Error::InvalidMsgTypeId(msg_type_id));
+    }
+
+    Ok(())
+}
+
+/// Space claimed in the ring buffer by [`Sender::try_claim`] or
+/// [`OneToOneSender::try_claim`](crate::one_to_one::OneToOneSender::try_claim).
+///
+/// Dereferences to the message bytes of the record. The record holds a negative length while
+/// claimed, which makes the receiver wait on it until it is either committed or aborted.
+pub struct Claim<'a> {
+    header: &'a RecordDescriptor,
+    buffer: &'a mut [u8],
+    descriptor: &'a SenderDescriptor,
+}
+
+impl<'a> Claim<'a> {
+    pub(crate) fn new(
+        header: &'a RecordDescriptor,
+        buffer: &'a mut [u8],
+        descriptor: &'a SenderDescriptor,
+    ) -> Self {
+        Self {
+            header,
+            buffer,
+            descriptor,
         }
     }
+
+    /// Publishes the record to the receiver.
+    pub fn commit(self) {
+        let length = self.header.length.load(Ordering::Relaxed);
+        debug_assert!(length < 0);
+        self.header.length.store(-length, Ordering::Release);
+        wake_consumer(self.descriptor);
+        mem::forget(self);
+    }
+
+    /// Turns the record into padding, which the receiver skips.
+    pub fn abort(self) {
+        drop(self);
+    }
+}
+
+impl Deref for Claim<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.buffer
+    }
+}
+
+impl DerefMut for Claim<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.buffer
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        let length = self.header.length.load(Ordering::Relaxed);
+        debug_assert!(length < 0);
+        self.header
+            .msg_type_id
+            .store(AERON_RB_PADDING_MSG_TYPE_ID, Ordering::Relaxed);
+        self.header.length.store(-length, Ordering::Release);
+        wake_consumer(self.descriptor);
+    }
 }
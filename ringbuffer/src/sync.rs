@@ -0,0 +1,12 @@
+//! Atomics used by the ring buffer protocol.
+//!
+//! Built with `RUSTFLAGS="--cfg loom"` these are swapped for loom's, so the protocol can be model
+//! checked with `cargo test --lib --release`. Loom atomics can't be placed in shared memory, which
+//! is why only ring buffers created through [`RingBuffer::new`](crate::RingBuffer::new) or a
+//! [fixed](crate::fixed) one can be used under loom.
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{AtomicI32, AtomicI64, AtomicU32, Ordering};
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicI32, AtomicI64, AtomicU32, Ordering};
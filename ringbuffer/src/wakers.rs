@@ -0,0 +1,109 @@
+//! Wakers of the async receiver and senders of a ring buffer.
+//!
+//! Unlike the rest of the ring buffer state, wakers can't be shared through the memory, they live
+//! next to it in the process: in the [`Backing`](crate::backing::Backing) of a runtime ring buffer
+//! and inline in a [fixed](crate::fixed) one. Only handles split from the same ring buffer wake
+//! each other.
+//!
+//! A task registers its waker, then checks the ring buffer again before returning
+//! `Poll::Pending`. The other side updates the ring buffer, then wakes whoever is registered. A
+//! `SeqCst` fence on both sides, between the store of one and the load of the other, guarantees
+//! that either the task sees the update or the other side sees the registration.
+
+#[cfg(all(feature = "async", not(loom)))]
+use std::{
+    mem,
+    sync::{
+        atomic::{fence, AtomicBool, Ordering},
+        Mutex,
+    },
+    task::Waker,
+};
+
+#[derive(Debug, Default)]
+pub(crate) struct Wakers {
+    #[cfg(all(feature = "async", not(loom)))]
+    receiver: Mutex<Option<Waker>>,
+    #[cfg(all(feature = "async", not(loom)))]
+    receiver_registered: AtomicBool,
+    #[cfg(all(feature = "async", not(loom)))]
+    senders: Mutex<Vec<Waker>>,
+    #[cfg(all(feature = "async", not(loom)))]
+    senders_registered: AtomicBool,
+}
+
+impl Wakers {
+    pub(crate) const fn new() -> Self {
+        Self {
+            #[cfg(all(feature = "async", not(loom)))]
+            receiver: Mutex::new(None),
+            #[cfg(all(feature = "async", not(loom)))]
+            receiver_registered: AtomicBool::new(false),
+            #[cfg(all(feature = "async", not(loom)))]
+            senders: Mutex::new(Vec::new()),
+            #[cfg(all(feature = "async", not(loom)))]
+            senders_registered: AtomicBool::new(false),
+        }
+    }
+
+    /// Registers the receiver task to be woken by the next published record.
+    #[cfg(all(feature = "async", not(loom)))]
+    pub(crate) fn register_receiver(&self, waker: &Waker) {
+        let mut receiver = self.receiver.lock().unwrap();
+        match &mut *receiver {
+            Some(registered) if registered.will_wake(waker) => {}
+            registered => *registered = Some(waker.clone()),
+        }
+        self.receiver_registered.store(true, Ordering::Relaxed);
+        drop(receiver);
+
+        fence(Ordering::SeqCst);
+    }
+
+    /// Registers a sender task to be woken once the receiver frees space.
+    #[cfg(all(feature = "async", not(loom)))]
+    pub(crate) fn register_sender(&self, waker: &Waker) {
+        let mut senders = self.senders.lock().unwrap();
+        if !senders.iter().any(|registered| registered.will_wake(waker)) {
+            senders.push(waker.clone());
+        }
+        self.senders_registered.store(true, Ordering::Relaxed);
+        drop(senders);
+
+        fence(Ordering::SeqCst);
+    }
+
+    /// Wakes the receiver task, if registered, after a record was published.
+    #[inline]
+    pub(crate) fn wake_receiver(&self) {
+        #[cfg(all(feature = "async", not(loom)))]
+        {
+            fence(Ordering::SeqCst);
+
+            if self.receiver_registered.load(Ordering::Relaxed)
+                && self.receiver_registered.swap(false, Ordering::Relaxed)
+            {
+                if let Some(waker) = self.receiver.lock().unwrap().take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    /// Wakes all registered sender tasks after the receiver freed space.
+    #[inline]
+    pub(crate) fn wake_senders(&self) {
+        #[cfg(all(feature = "async", not(loom)))]
+        {
+            fence(Ordering::SeqCst);
+
+            if self.senders_registered.load(Ordering::Relaxed)
+                && self.senders_registered.swap(false, Ordering::Relaxed)
+            {
+                for waker in mem::take(&mut *self.senders.lock().unwrap()) {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}